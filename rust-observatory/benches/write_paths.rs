@@ -0,0 +1,98 @@
+//! Benchmark: three ways to send an HTTP response (head + body) over a socket.
+//!
+//!     cargo bench --bench write_paths
+//!
+//! * two writes  - write_all(head) then write_all(body): 2 syscalls
+//! * concat      - copy head + body into one Vec, then write_all: 1 syscall + a copy
+//! * vectored    - write_vectored([head, body]) (writev): 1 syscall, no copy
+//!
+//! The server uses the vectored path. Each variant sends to a Unix socket pair
+//! whose other end is drained by a thread, and counts the write calls that
+//! reach the socket (each one is a syscall).
+
+use std::io::{IoSlice, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Instant;
+
+const ITERATIONS: usize = 100_000;
+
+/// Counts write calls that reach the socket.
+struct Counting {
+    inner: UnixStream,
+    calls: usize,
+}
+
+impl Write for Counting {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.calls += 1;
+        self.inner.write(buf)
+    }
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        self.calls += 1;
+        self.inner.write_vectored(bufs)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn write_all_vectored(w: &mut impl Write, mut bufs: &mut [IoSlice<'_>]) -> std::io::Result<()> {
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        let n = w.write_vectored(bufs)?;
+        IoSlice::advance_slices(&mut bufs, n);
+    }
+    Ok(())
+}
+
+fn run(name: &str, body: &[u8], send: fn(&mut Counting, &[u8], &[u8])) {
+    let (tx, mut rx) = UnixStream::pair().unwrap();
+    let drain = std::thread::spawn(move || {
+        let mut buf = vec![0u8; 1 << 16];
+        while rx.read(&mut buf).unwrap_or(0) > 0 {}
+    });
+    let mut out = Counting {
+        inner: tx,
+        calls: 0,
+    };
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    );
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        send(&mut out, head.as_bytes(), body);
+    }
+    let elapsed = start.elapsed();
+    let calls = out.calls;
+    drop(out);
+    drain.join().unwrap();
+
+    println!(
+        "  {:<11} {:>7.0} ns/response  {:.2} syscalls/response",
+        name,
+        elapsed.as_nanos() as f64 / ITERATIONS as f64,
+        calls as f64 / ITERATIONS as f64,
+    );
+}
+
+fn main() {
+    for size in [0, 256, 16 * 1024] {
+        let body = vec![b'x'; size];
+        println!("body = {} bytes", size);
+        run("two writes", &body, |out, head, body| {
+            out.write_all(head).unwrap();
+            out.write_all(body).unwrap();
+        });
+        run("concat", &body, |out, head, body| {
+            let mut buf = Vec::with_capacity(head.len() + body.len());
+            buf.extend_from_slice(head);
+            buf.extend_from_slice(body);
+            out.write_all(&buf).unwrap();
+        });
+        run("vectored", &body, |out, head, body| {
+            write_all_vectored(out, &mut [IoSlice::new(head), IoSlice::new(body)]).unwrap();
+        });
+    }
+}
@@ -0,0 +1,341 @@
+//! Client for sending hook events to a running observatory.
+//!
+//! This is the same request Claude Code's `curl` hook makes: `POST
+//! /hook?event=NAME` with the payload as a JSON body. Rust hook helpers and
+//! the `send` subcommand use it instead of shelling out to curl.
+//!
+//! ```no_run
+//! use rust_observatory::client::ObservatoryClient;
+//!
+//! let client = ObservatoryClient::tcp("127.0.0.1:23518").retries(2);
+//! let response = client.send("PreToolUse", &serde_json::json!({"tool_name": "Bash"}))?;
+//! assert_eq!(response.status, 200);
+//! # Ok::<(), rust_observatory::client::ClientError>(())
+//! ```
+//!
+//! Each send opens a fresh connection; `connect` keeps one alive for a
+//! sender that posts many events in a row. Connect and write failures are
+//! retried with a doubling delay. A request whose answer doesn't come back
+//! (a read timeout, a dropped connection) is not: the server may already
+//! have published it, and sending it again would show it twice. An HTTP
+//! error status is an answer, not a failure, and is never retried either.
+//!
+//! TCP and Unix sockets only. Windows named pipes aren't supported: the
+//! observatory itself is Unix-only (peer credentials, signals, flock), so
+//! there is nothing on Windows to send to except a TCP port.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde_json::Value;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Where the observatory listens.
+#[derive(Clone, Debug)]
+pub enum Endpoint {
+    Tcp(String), // "host:port"
+    Unix(PathBuf),
+}
+
+/// The server's answer. A 200 with an empty body means "proceed".
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// Connecting or writing the request failed (after all retries).
+    Io(std::io::Error),
+    /// The request went out but no answer came back. Never retried: the
+    /// server may have published the event.
+    NoAnswer(std::io::Error),
+    /// The server answered with something that isn't HTTP.
+    BadResponse(String),
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "{}", e),
+            ClientError::NoAnswer(e) => write!(f, "no answer: {}", e),
+            ClientError::BadResponse(why) => write!(f, "bad response: {}", why),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<std::io::Error> for ClientError {
+    fn from(e: std::io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ObservatoryClient {
+    endpoint: Endpoint,
+    timeout: Duration,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl ObservatoryClient {
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            timeout: DEFAULT_TIMEOUT,
+            retries: 0,
+            retry_delay: DEFAULT_RETRY_DELAY,
+        }
+    }
+
+    pub fn tcp(addr: impl Into<String>) -> Self {
+        Self::new(Endpoint::Tcp(addr.into()))
+    }
+
+    pub fn unix(path: impl Into<PathBuf>) -> Self {
+        Self::new(Endpoint::Unix(path.into()))
+    }
+
+    /// Connect, write and read timeout for each attempt (default 5s).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Extra attempts after a connect or write failure (default 0).
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Delay before the first retry; doubles for each one after (default 100ms).
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// POST one event on a fresh connection, retrying connect and write failures.
+    pub fn send(&self, event_type: &str, payload: &Value) -> Result<Response, ClientError> {
+        let request = build_request(event_type, &payload.to_string(), false);
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+        loop {
+            match self
+                .connect()
+                .and_then(|mut c| c.round_trip(request.as_bytes()))
+            {
+                Err(ClientError::Io(_)) if attempt < self.retries => {
+                    attempt += 1;
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Open a keep-alive connection. Its sends are never retried.
+    pub fn connect(&self) -> Result<Connection, ClientError> {
+        let stream = match &self.endpoint {
+            Endpoint::Tcp(addr) => {
+                let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::NotFound, "host did not resolve")
+                })?;
+                let stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+                stream.set_nodelay(true)?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                Stream::Tcp(stream)
+            }
+            Endpoint::Unix(path) => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                Stream::Unix(stream)
+            }
+        };
+        Ok(Connection {
+            stream,
+            buf: Vec::new(),
+        })
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+/// One open connection to the observatory (see `ObservatoryClient::connect`).
+pub struct Connection {
+    stream: Stream,
+    buf: Vec<u8>, // bytes read past the last response
+}
+
+impl Connection {
+    /// POST one event and wait for its answer.
+    pub fn send(&mut self, event_type: &str, payload: &Value) -> Result<Response, ClientError> {
+        let request = build_request(event_type, &payload.to_string(), true);
+        self.round_trip(request.as_bytes())
+    }
+
+    fn round_trip(&mut self, request: &[u8]) -> Result<Response, ClientError> {
+        let written = match &mut self.stream {
+            Stream::Tcp(stream) => stream.write_all(request),
+            Stream::Unix(stream) => stream.write_all(request),
+        };
+        written?;
+        let raw = self.read_response().map_err(ClientError::NoAnswer)?;
+        parse_response(&raw)
+    }
+
+    /// Read up to the end of one response: its headers, then Content-Length
+    /// bytes of body (to EOF without one).
+    fn read_response(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut chunk = [0u8; 8192];
+        let mut eof = false;
+        loop {
+            if let Some(end) = response_len(&self.buf, eof) {
+                return Ok(self.buf.drain(..end).collect());
+            }
+            if eof {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let n = match &mut self.stream {
+                Stream::Tcp(stream) => stream.read(&mut chunk)?,
+                Stream::Unix(stream) => stream.read(&mut chunk)?,
+            };
+            eof = n == 0;
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+/// The length of the complete response at the start of `buf`, if it's all in.
+fn response_len(buf: &[u8], eof: bool) -> Option<usize> {
+    let head_end = buf.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+    let head = String::from_utf8_lossy(&buf[..head_end]);
+    let length = head.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("content-length")
+            .then(|| value.trim().parse::<usize>().ok())?
+    });
+    match length {
+        Some(length) => (buf.len() >= head_end + length).then_some(head_end + length),
+        None => (eof && !buf.is_empty()).then_some(buf.len()),
+    }
+}
+
+fn build_request(event_type: &str, body: &str, keep_alive: bool) -> String {
+    format!(
+        "POST /hook?event={} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}\r\n{}",
+        encode_query_value(event_type),
+        body.len(),
+        if keep_alive { "" } else { "Connection: close\r\n" },
+        body
+    )
+}
+
+/// Percent-encode everything but unreserved characters (RFC 3986).
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn parse_response(raw: &[u8]) -> Result<Response, ClientError> {
+    let text = String::from_utf8_lossy(raw);
+    let (head, body) = text
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| ClientError::BadResponse("no header terminator".into()))?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .filter(|_| head.starts_with("HTTP/"))
+        .ok_or_else(|| {
+            ClientError::BadResponse(format!("status line {:?}", head.lines().next()))
+        })?;
+    Ok(Response {
+        status,
+        body: body.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_request_encodes_event_name() {
+        let request = build_request("Pre Tool&Use", "{}", false);
+        assert!(request.starts_with("POST /hook?event=Pre%20Tool%26Use HTTP/1.1\r\n"));
+        assert!(request.ends_with("Content-Length: 2\r\nConnection: close\r\n\r\n{}"));
+        let request = build_request("Stop", "{}", true);
+        assert!(request.ends_with("Content-Length: 2\r\n\r\n{}"));
+    }
+
+    #[test]
+    fn test_parse_response_and_retry_exhaustion() {
+        let response = parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}").unwrap();
+        assert_eq!((response.status, response.body.as_str()), (200, "{}"));
+        assert!(matches!(
+            parse_response(b"garbage"),
+            Err(ClientError::BadResponse(_))
+        ));
+
+        // Framed on Content-Length, so a kept-alive connection's next answer stays buffered
+        let two = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}HTTP/1.1 200 OK\r\n";
+        assert_eq!(response_len(two, false), Some(two.len() - 17));
+        assert_eq!(response_len(&two[..30], false), None);
+        assert_eq!(response_len(b"HTTP/1.0 200 OK\r\n\r\nok", true), Some(21));
+
+        // Nothing listens here: every attempt fails, the last error is returned
+        let client = ObservatoryClient::unix("/nonexistent/observatory.sock")
+            .retries(2)
+            .retry_delay(Duration::from_millis(1));
+        assert!(matches!(
+            client.send("Stop", &serde_json::json!({})),
+            Err(ClientError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_unanswered_request_is_not_retried() {
+        let path = std::env::temp_dir().join(format!("obs-client-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        listener.set_nonblocking(true).unwrap();
+
+        // Reads the request and never answers
+        let client = ObservatoryClient::unix(&path)
+            .timeout(Duration::from_millis(200))
+            .retries(2)
+            .retry_delay(Duration::from_millis(1));
+        assert!(matches!(
+            client.send("PreToolUse", &serde_json::json!({})),
+            Err(ClientError::NoAnswer(_))
+        ));
+        let mut accepted = 0;
+        while listener.accept().is_ok() {
+            accepted += 1;
+        }
+        assert_eq!(accepted, 1);
+        let _ = std::fs::remove_file(&path);
+    }
+}
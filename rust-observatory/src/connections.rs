@@ -0,0 +1,974 @@
+//! Client connections: the accept loops, one task per connection (read a
+//! request, hand it to the main thread, write the reply) and the output
+//! socket's readers. The code is written once, as async fns, and runs on
+//! one of two runtimes:
+//!
+//! - with the `async` feature, tokio: the listener wakes on readiness, and
+//!   every connection and output reader is a task, so a few --workers
+//!   threads serve them all;
+//! - without it, a thread per connection and per output reader, blocking in
+//!   accept(), read() and write(), with --header-timeout and --read-timeout
+//!   kept as socket read timeouts. Nothing there ever waits for a wakeup,
+//!   so `block_on` only has to poll once.
+//!
+//! Only the section below, RUNTIME, differs between the two.
+
+use std::future::Future;
+
+use super::*;
+
+/// --max-connections unless given: no cap when a connection is a task,
+/// and this many when each costs a thread and its stack.
+const DEFAULT_MAX_CONNECTIONS: Option<u32> = if cfg!(feature = "async") {
+    None
+} else {
+    Some(512)
+};
+
+// === RUNTIME ===
+
+/// The way back to a connection, for its request's reply.
+#[cfg(feature = "async")]
+pub(crate) type ReplyTo = tokio::sync::oneshot::Sender<HttpReply>;
+#[cfg(not(feature = "async"))]
+pub(crate) type ReplyTo = std::sync::mpsc::SyncSender<HttpReply>;
+
+/// What wakes an output reader once lines are queued (without `async`,
+/// waited on under the queue's lock).
+#[cfg(feature = "async")]
+pub(crate) type Ready = tokio::sync::Notify;
+#[cfg(not(feature = "async"))]
+pub(crate) type Ready = std::sync::Condvar;
+
+/// A connection's stream, TCP or Unix.
+trait Socket: Send + 'static {
+    /// Append what has arrived to `buf`, as much as its capacity allows.
+    /// Fails with `TimedOut` once `by` has passed; None waits for good.
+    fn read_into(
+        &mut self,
+        buf: &mut Vec<u8>,
+        by: Option<std::time::Instant>,
+    ) -> impl Future<Output = std::io::Result<usize>> + Send;
+
+    /// Write `head` then `body`, in one writev(2) when the socket has room.
+    fn send(
+        &mut self,
+        head: &[u8],
+        body: &[u8],
+    ) -> impl Future<Output = std::io::Result<()>> + Send;
+
+    /// Close the sending side; reads still work.
+    fn shutdown_write(&mut self) -> impl Future<Output = ()> + Send;
+}
+
+/// A listener, handing out connections.
+trait Acceptor: Send + 'static {
+    type Conn: Socket + std::os::unix::io::AsRawFd;
+    type Addr;
+
+    fn accept_next(&self)
+        -> impl Future<Output = std::io::Result<(Self::Conn, Self::Addr)>> + Send;
+}
+
+#[cfg(feature = "async")]
+impl<S> Socket for S
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    async fn read_into(
+        &mut self,
+        buf: &mut Vec<u8>,
+        by: Option<std::time::Instant>,
+    ) -> std::io::Result<usize> {
+        use tokio::io::AsyncReadExt;
+        match by {
+            Some(by) => tokio::time::timeout_at(by.into(), self.read_buf(buf))
+                .await
+                .unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into())),
+            None => self.read_buf(buf).await,
+        }
+    }
+
+    async fn send(&mut self, head: &[u8], body: &[u8]) -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+        let mut slices = [std::io::IoSlice::new(head), std::io::IoSlice::new(body)];
+        let mut bufs = &mut slices[..];
+        std::io::IoSlice::advance_slices(&mut bufs, 0); // skip leading empty slices
+        while !bufs.is_empty() {
+            match self.write_vectored(bufs).await? {
+                0 => return Err(std::io::ErrorKind::WriteZero.into()),
+                n => std::io::IoSlice::advance_slices(&mut bufs, n),
+            }
+        }
+        self.flush().await
+    }
+
+    async fn shutdown_write(&mut self) {
+        use tokio::io::AsyncWriteExt;
+        let _ = self.shutdown().await;
+    }
+}
+
+#[cfg(feature = "async")]
+impl Acceptor for tokio::net::TcpListener {
+    type Conn = tokio::net::TcpStream;
+    type Addr = std::net::SocketAddr;
+
+    async fn accept_next(&self) -> std::io::Result<(Self::Conn, Self::Addr)> {
+        self.accept().await
+    }
+}
+
+#[cfg(feature = "async")]
+impl Acceptor for tokio::net::UnixListener {
+    type Conn = tokio::net::UnixStream;
+    type Addr = tokio::net::unix::SocketAddr;
+
+    async fn accept_next(&self) -> std::io::Result<(Self::Conn, Self::Addr)> {
+        self.accept().await
+    }
+}
+
+/// The std streams: reads block for at most the time left before the
+/// deadline, set as SO_RCVTIMEO before each one.
+#[cfg(not(feature = "async"))]
+trait BlockingSocket: Read + Write + Send + 'static {
+    fn set_timeout(&self, timeout: Option<std::time::Duration>) -> std::io::Result<()>;
+    fn shutdown_write(&self);
+}
+
+#[cfg(not(feature = "async"))]
+impl BlockingSocket for std::net::TcpStream {
+    fn set_timeout(&self, timeout: Option<std::time::Duration>) -> std::io::Result<()> {
+        self.set_read_timeout(timeout)
+    }
+
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(std::net::Shutdown::Write);
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl BlockingSocket for UnixStream {
+    fn set_timeout(&self, timeout: Option<std::time::Duration>) -> std::io::Result<()> {
+        self.set_read_timeout(timeout)
+    }
+
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(std::net::Shutdown::Write);
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl<S: BlockingSocket> Socket for S {
+    async fn read_into(
+        &mut self,
+        buf: &mut Vec<u8>,
+        by: Option<std::time::Instant>,
+    ) -> std::io::Result<usize> {
+        let left = match by {
+            Some(by) => match by.saturating_duration_since(std::time::Instant::now()) {
+                left if left.is_zero() => return Err(std::io::ErrorKind::TimedOut.into()),
+                left => Some(left),
+            },
+            None => None,
+        };
+        self.set_timeout(left)?;
+        let filled = buf.len();
+        buf.resize(buf.capacity().max(filled + 1), 0);
+        let read = match self.read(&mut buf[filled..]) {
+            // What an expired SO_RCVTIMEO reports
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                Err(std::io::ErrorKind::TimedOut.into())
+            }
+            read => read,
+        };
+        buf.truncate(filled + read.as_ref().map_or(0, |&n| n));
+        read
+    }
+
+    async fn send(&mut self, head: &[u8], body: &[u8]) -> std::io::Result<()> {
+        write_all_vectored(
+            self,
+            &mut [std::io::IoSlice::new(head), std::io::IoSlice::new(body)],
+        )
+    }
+
+    async fn shutdown_write(&mut self) {
+        BlockingSocket::shutdown_write(self);
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl Acceptor for TcpListener {
+    type Conn = std::net::TcpStream;
+    type Addr = std::net::SocketAddr;
+
+    async fn accept_next(&self) -> std::io::Result<(Self::Conn, Self::Addr)> {
+        self.accept()
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl Acceptor for UnixListener {
+    type Conn = UnixStream;
+    type Addr = std::os::unix::net::SocketAddr;
+
+    async fn accept_next(&self) -> std::io::Result<(Self::Conn, Self::Addr)> {
+        self.accept()
+    }
+}
+
+/// Run `task` on its own: a tokio task, or a thread.
+#[cfg(feature = "async")]
+fn spawn(task: impl Future<Output = ()> + Send + 'static) {
+    tokio::spawn(task);
+}
+
+#[cfg(not(feature = "async"))]
+fn spawn(task: impl Future<Output = ()> + Send + 'static) {
+    std::thread::spawn(move || block_on(task));
+}
+
+/// Drive a future whose every step blocks until it is done, as all of them
+/// do without `async`: it is ready the first time it is polled.
+#[cfg(not(feature = "async"))]
+fn block_on<T>(task: impl Future<Output = T>) -> T {
+    let mut task = std::pin::pin!(task);
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    match task.as_mut().poll(&mut context) {
+        std::task::Poll::Ready(output) => output,
+        std::task::Poll::Pending => unreachable!("a blocking connection waited for a wakeup"),
+    }
+}
+
+#[cfg(feature = "async")]
+async fn sleep(duration: std::time::Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+#[cfg(not(feature = "async"))]
+async fn sleep(duration: std::time::Duration) {
+    std::thread::sleep(duration);
+}
+
+/// Hand a request to the main thread and wait for its reply; None once the
+/// main thread has stopped.
+#[cfg(feature = "async")]
+async fn ask(jobs: &std::sync::mpsc::Sender<Wake>, request: HttpRequest) -> Option<HttpReply> {
+    let (reply_to, reply) = tokio::sync::oneshot::channel();
+    jobs.send(Wake::Request(request, reply_to)).ok()?;
+    reply.await.ok()
+}
+
+#[cfg(not(feature = "async"))]
+async fn ask(jobs: &std::sync::mpsc::Sender<Wake>, request: HttpRequest) -> Option<HttpReply> {
+    let (reply_to, reply) = std::sync::mpsc::sync_channel(1);
+    jobs.send(Wake::Request(request, reply_to)).ok()?;
+    reply.recv().ok()
+}
+
+/// Wait until lines are queued for `reader`, or it is closed.
+#[cfg(feature = "async")]
+async fn wait_ready(reader: &OutputReader) {
+    reader.ready.notified().await;
+}
+
+#[cfg(not(feature = "async"))]
+async fn wait_ready(reader: &OutputReader) {
+    let queue = reader.queue.lock().unwrap();
+    let _queue = reader
+        .ready
+        .wait_while(queue, |queue| {
+            queue.is_empty() && !reader.closed.load(Ordering::SeqCst)
+        })
+        .unwrap();
+}
+
+/// Serve the output socket from a thread of its own (with `async`, a
+/// runtime on that thread).
+#[cfg(feature = "async")]
+pub(crate) fn serve_output_readers(
+    listener: UnixListener,
+    readers: Arc<Mutex<Vec<Arc<OutputReader>>>>,
+) -> std::io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let listener = {
+        let _context = runtime.enter(); // from_std registers with this runtime
+        tokio::net::UnixListener::from_std(listener)?
+    };
+    std::thread::spawn(move || runtime.block_on(accept_output_readers(listener, readers)));
+    Ok(())
+}
+
+#[cfg(not(feature = "async"))]
+pub(crate) fn serve_output_readers(
+    listener: UnixListener,
+    readers: Arc<Mutex<Vec<Arc<OutputReader>>>>,
+) -> std::io::Result<()> {
+    std::thread::spawn(move || block_on(accept_output_readers(listener, readers)));
+    Ok(())
+}
+
+/// Serve `listener` from an accept loop that starts a task for each
+/// connection: with `async` on a runtime of --workers threads, without it
+/// on a thread of its own. Parsed requests and shutdown signals arrive on
+/// `jobs`, to be handled by `serve_requests`.
+#[cfg(feature = "async")]
+pub(crate) fn spawn_server(
+    listener: Listener,
+    common: &CommonArgs,
+    running: &Arc<AtomicBool>,
+    jobs: std::sync::mpsc::Sender<Wake>,
+) -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (limits, slots) = connection_limits(common);
+    match &listener {
+        Listener::Tcp(l) => l.set_nonblocking(true)?,
+        Listener::Unix(l) => l.set_nonblocking(true)?,
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(common.workers as usize)
+        .enable_all()
+        .build()?;
+    let accept_loop = {
+        let _context = runtime.enter(); // from_std registers with this runtime
+        tokio::spawn(forward_shutdown(
+            signal(SignalKind::interrupt())?,
+            signal(SignalKind::terminate())?,
+            jobs.clone(),
+            running.clone(),
+        ));
+        match listener {
+            Listener::Tcp(l) => tokio::spawn(accept_tcp(
+                tokio::net::TcpListener::from_std(l)?,
+                limits,
+                slots,
+                jobs,
+            )),
+            Listener::Unix(l) => tokio::spawn(accept_unix(
+                tokio::net::UnixListener::from_std(l)?,
+                limits,
+                slots,
+                jobs,
+            )),
+        }
+    };
+    std::thread::spawn(move || runtime.block_on(accept_loop));
+    Ok(())
+}
+
+#[cfg(not(feature = "async"))]
+pub(crate) fn spawn_server(
+    listener: Listener,
+    common: &CommonArgs,
+    running: &Arc<AtomicBool>,
+    jobs: std::sync::mpsc::Sender<Wake>,
+) -> std::io::Result<()> {
+    let (limits, slots) = connection_limits(common);
+    {
+        let (jobs, running) = (jobs.clone(), running.clone());
+        std::thread::spawn(move || forward_shutdown(jobs, running));
+    }
+    match listener {
+        Listener::Tcp(l) => spawn(accept_tcp(l, limits, slots, jobs)),
+        Listener::Unix(l) => spawn(accept_unix(l, limits, slots, jobs)),
+    }
+    Ok(())
+}
+
+/// Wake the main thread on Ctrl+C or SIGTERM, so it stops at once even
+/// when idle.
+#[cfg(feature = "async")]
+async fn forward_shutdown(
+    mut interrupt: tokio::signal::unix::Signal,
+    mut terminate: tokio::signal::unix::Signal,
+    jobs: std::sync::mpsc::Sender<Wake>,
+    running: Arc<AtomicBool>,
+) {
+    tokio::select! {
+        _ = interrupt.recv() => {}
+        _ = terminate.recv() => {}
+    }
+    running.store(false, Ordering::SeqCst);
+    let _ = jobs.send(Wake::Shutdown);
+}
+
+/// How often `forward_shutdown` looks for Ctrl+C or SIGTERM.
+#[cfg(not(feature = "async"))]
+const SHUTDOWN_POLL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Wake the main thread once Ctrl+C or SIGTERM has cleared `running` (see
+/// `ctrlc_handler`), so it stops even when idle.
+#[cfg(not(feature = "async"))]
+fn forward_shutdown(jobs: std::sync::mpsc::Sender<Wake>, running: Arc<AtomicBool>) {
+    while running.load(Ordering::SeqCst) {
+        std::thread::sleep(SHUTDOWN_POLL);
+    }
+    let _ = jobs.send(Wake::Shutdown);
+}
+
+// === CONNECTIONS ===
+
+impl RequestBuffer {
+    /// Read until the current request's headers are in (or EOF), so they
+    /// can have a deadline of their own; `read_request_by` then reads the
+    /// body.
+    async fn read_head_by(
+        &mut self,
+        socket: &mut impl Socket,
+        by: Option<std::time::Instant>,
+    ) -> std::io::Result<()> {
+        self.begin();
+        while self.head_len()?.is_none() {
+            self.make_room();
+            if socket.read_into(&mut self.data, by).await? == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// `read_request` from a connection, by a deadline. A client that sent
+    /// `Expect: 100-continue` is told to go on once its headers are
+    /// accepted, if the body isn't already on its way.
+    async fn read_request_by(
+        &mut self,
+        socket: &mut impl Socket,
+        by: Option<std::time::Instant>,
+    ) -> std::io::Result<&[u8]> {
+        self.begin();
+        let mut continued = false;
+        while !self.complete()? {
+            if self.expect_continue && !continued {
+                socket.send(b"HTTP/1.1 100 Continue\r\n\r\n", b"").await?;
+                continued = true;
+            }
+            self.make_room();
+            if socket.read_into(&mut self.data, by).await? == 0 {
+                return self.end_of_stream();
+            }
+        }
+        Ok(self.finish())
+    }
+}
+
+/// `within` from now; None (no deadline) if that is past the end of time.
+fn deadline(within: std::time::Duration) -> Option<std::time::Instant> {
+    std::time::Instant::now().checked_add(within)
+}
+
+/// `write_http_response_as` for a connection, with any extra headers.
+async fn write_http_response_to(
+    socket: &mut impl Socket,
+    status: u16,
+    content_type: &str,
+    headers: &[(&str, String)],
+    body: &str,
+) -> std::io::Result<()> {
+    let head = http_response_head(status, content_type, body.len(), headers);
+    socket.send(head.as_bytes(), body.as_bytes()).await
+}
+
+/// Accept output readers, each with a task writing out its queue.
+async fn accept_output_readers(
+    listener: impl Acceptor,
+    readers: Arc<Mutex<Vec<Arc<OutputReader>>>>,
+) {
+    let mut next_id = 0;
+    let mut failures = 0;
+    loop {
+        let client = match listener.accept_next().await {
+            Ok((client, _)) => client,
+            Err(e) => {
+                accept_failed("an output reader", e, &mut failures).await;
+                continue;
+            }
+        };
+        next_id += 1;
+        let reader = Arc::new(OutputReader::new(next_id));
+        let total = {
+            let mut readers = readers.lock().unwrap();
+            readers.push(reader.clone());
+            readers
+                .iter()
+                .filter(|r| !r.closed.load(Ordering::SeqCst))
+                .count()
+        };
+        tracing::info!(reader = next_id, total, "Output reader connected");
+        spawn(feed_output_reader(client, reader));
+    }
+}
+
+/// Write one reader's lines until it goes away or is cut off. Lines that
+/// queued up while a write was blocked go out together in the next write,
+/// so a reader that fell behind catches up in a few large writes.
+async fn feed_output_reader(mut client: impl Socket, reader: Arc<OutputReader>) {
+    let mut pending = Vec::new();
+    while !reader.closed.load(Ordering::SeqCst) {
+        reader.take(&mut pending);
+        if pending.is_empty() {
+            wait_ready(&reader).await;
+            continue;
+        }
+        // A partial write leaves the socket buffer full; `send` waits for
+        // room instead of giving up on the reader
+        if let Err(e) = client.send(&pending, b"").await {
+            tracing::info!(reader = reader.id, error = %e, "Output reader disconnected");
+            break;
+        }
+        pending.clear();
+    }
+    reader.close();
+}
+
+/// Keep a connection open without answering until the peer closes it.
+async fn hold_until_closed(socket: &mut impl Socket) {
+    let mut buf = Vec::with_capacity(512);
+    // Closed (or failed) ends it; anything sent is ignored
+    while matches!(socket.read_into(&mut buf, None).await, Ok(n) if n > 0) {
+        buf.clear();
+    }
+}
+
+/// What each connection is held to, and the --max-connections slots.
+fn connection_limits(common: &CommonArgs) -> (ConnectionLimits, ConnectionSlots) {
+    let limits = ConnectionLimits {
+        header_timeout: common.header_timeout,
+        read_timeout: common.read_timeout,
+        max_body: usize::try_from(common.max_body).unwrap_or(usize::MAX),
+    };
+    let slots = common
+        .max_connections
+        .or(DEFAULT_MAX_CONNECTIONS)
+        .map(|n| Arc::new(std::sync::atomic::AtomicUsize::new(n as usize)));
+    (limits, slots)
+}
+
+/// --max-connections: how many more connections may open. None means no
+/// cap.
+type ConnectionSlots = Option<Arc<std::sync::atomic::AtomicUsize>>;
+
+/// One open connection's place under --max-connections, given back when
+/// its task is done with it.
+struct Slot(Arc<std::sync::atomic::AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Take a slot for a new connection: Some(slot or no cap), or None when
+/// all are in use. Refusals are reported at 1, 2, 4, 8, ...
+fn take_slot(slots: &ConnectionSlots, refused: &mut u64) -> Option<Option<Slot>> {
+    let Some(slots) = slots else {
+        return Some(None);
+    };
+    match slots.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |free| {
+        free.checked_sub(1)
+    }) {
+        Ok(_) => Some(Some(Slot(slots.clone()))),
+        Err(_) => {
+            report_refused_connection(refused);
+            None
+        }
+    }
+}
+
+/// Report a failed accept(), then back off.
+async fn accept_failed(what: &str, error: std::io::Error, failures: &mut u64) {
+    report_accept_failure(what, &error, failures);
+    sleep(ACCEPT_BACKOFF).await;
+}
+
+/// Answer a TCP connection over --max-connections with a 503 and close it.
+async fn refuse_tcp(mut socket: impl Socket) {
+    let body = error_json("too_many_connections", "over --max-connections, try again");
+    refuse(&mut socket, 503, &body).await;
+}
+
+/// Send an error reply and close. Whatever the client still sends is read
+/// and discarded for a moment first: closing with unread data resets the
+/// connection, and the client could lose the answer.
+async fn refuse(socket: &mut impl Socket, status: u16, body: &str) {
+    let close = [("Connection", "close".to_string())];
+    if write_http_response_to(socket, status, "application/json", &close, body)
+        .await
+        .is_err()
+    {
+        return;
+    }
+    socket.shutdown_write().await;
+    let mut discard = Vec::with_capacity(4096);
+    let by = deadline(REFUSE_LINGER);
+    while matches!(socket.read_into(&mut discard, by).await, Ok(n) if n > 0) {
+        discard.clear();
+    }
+}
+
+async fn accept_tcp(
+    listener: impl Acceptor<Addr = std::net::SocketAddr>,
+    limits: ConnectionLimits,
+    slots: ConnectionSlots,
+    jobs: std::sync::mpsc::Sender<Wake>,
+) {
+    let mut refused = 0;
+    let mut failures = 0;
+    loop {
+        let (stream, addr) = match listener.accept_next().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                accept_failed("a connection", e, &mut failures).await;
+                continue;
+            }
+        };
+        let Some(slot) = take_slot(&slots, &mut refused) else {
+            spawn(refuse_tcp(stream));
+            continue;
+        };
+        tracing::debug!(client = %addr, "Connection accepted");
+        let peer = PeerInfo::Tcp {
+            client_addr: addr.ip().to_string(),
+        };
+        let jobs = jobs.clone();
+        spawn(async move {
+            serve_connection(stream, peer, limits, jobs).await;
+            drop(slot);
+        });
+    }
+}
+
+/// Like `accept_tcp`, but a connection over --max-connections is simply
+/// closed: local clients (the curl hook) see the failure at once.
+async fn accept_unix(
+    listener: impl Acceptor,
+    limits: ConnectionLimits,
+    slots: ConnectionSlots,
+    jobs: std::sync::mpsc::Sender<Wake>,
+) {
+    let mut refused = 0;
+    let mut failures = 0;
+    loop {
+        let (stream, _) = match listener.accept_next().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                accept_failed("a connection", e, &mut failures).await;
+                continue;
+            }
+        };
+        let Some(slot) = take_slot(&slots, &mut refused) else {
+            continue; // dropped: closed
+        };
+        let peer = get_peer_creds(&stream);
+        if let PeerInfo::Unix { pid, uid, .. } = peer {
+            tracing::debug!(pid, uid, "Connection accepted");
+        }
+        let jobs = jobs.clone();
+        spawn(async move {
+            serve_connection(stream, peer, limits, jobs).await;
+            drop(slot);
+        });
+    }
+}
+
+/// One connection: read, hand over to the main thread, write the reply,
+/// then wait for the next request (HTTP/1.1 keep-alive) until the client
+/// sends `Connection: close` or closes its side. Requests already buffered
+/// behind the current one (pipelining) are answered in order without
+/// another read. A client that doesn't finish a request within
+/// --read-timeout gets a 408 and is closed; nothing it sent is published.
+/// One that sends nothing for that long after an answer is closed quietly.
+/// A Content-Length over --max-body gets a 413 before any body is read.
+async fn serve_connection(
+    mut socket: impl Socket,
+    peer: PeerInfo,
+    limits: ConnectionLimits,
+    jobs: std::sync::mpsc::Sender<Wake>,
+) {
+    let mut read_buf = RequestBuffer::new().with_max_body(limits.max_body);
+    let mut idle = false; // between requests, once one was answered
+    loop {
+        // Separate deadlines, so a client trickling its headers is cut off
+        // as surely as one trickling its body
+        let head = read_buf.read_head_by(&mut socket, deadline(limits.header_timeout));
+        let read = match head.await {
+            Ok(()) => {
+                read_buf
+                    .read_request_by(&mut socket, deadline(limits.read_timeout))
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        let (method, path, version, body, headers) = match read {
+            Ok(data) if !data.is_empty() => parse_http_request(data),
+            Err(e) => {
+                // An idle keep-alive connection is closed quietly
+                let timed_out = e.kind() == std::io::ErrorKind::TimedOut;
+                if timed_out && idle && !read_buf.pending() {
+                    return;
+                }
+                let Some((status, code, message)) = read_refusal(&e, limits.max_body) else {
+                    return;
+                };
+                let _ = jobs.send(Wake::Refused(status));
+                let body = error_json(code, &message);
+                if timed_out {
+                    let close = [("Connection", "close".to_string())];
+                    let _ = write_http_response_to(
+                        &mut socket,
+                        status,
+                        "application/json",
+                        &close,
+                        &body,
+                    )
+                    .await;
+                } else {
+                    refuse(&mut socket, status, &body).await;
+                }
+                return;
+            }
+            Ok(_) => return,
+        };
+        let received = std::time::Instant::now();
+        let keep_alive = keeps_alive(&version, headers.get("connection").map(String::as_str));
+        let timed_method = method.clone();
+        let request = HttpRequest {
+            method,
+            path,
+            body,
+            headers,
+            peer: peer.clone(),
+            received,
+        };
+        let Some(mut reply) = ask(&jobs, request).await else {
+            return; // the main thread stopped
+        };
+        if reply.hold {
+            hold_until_closed(&mut socket).await;
+            return;
+        }
+        if let Some(delay) = reply.delay {
+            sleep(delay).await;
+        }
+        reply.announce_connection(keep_alive, &version);
+        let written = if reply.head {
+            // Content-Length is still that of the GET body
+            let head = http_response_head(
+                reply.status,
+                reply.content_type,
+                reply.body.len(),
+                &reply.headers,
+            );
+            socket.send(head.as_bytes(), b"").await
+        } else {
+            write_http_response_to(
+                &mut socket,
+                reply.status,
+                reply.content_type,
+                &reply.headers,
+                &reply.body,
+            )
+            .await
+        };
+        if written.is_err() {
+            return;
+        }
+        let _ = jobs.send(Wake::Answered(timed_method, received.elapsed()));
+        if !keep_alive {
+            return;
+        }
+        idle = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a test on the runtime connections use.
+    fn run(test: impl Future<Output = ()>) {
+        #[cfg(feature = "async")]
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(test);
+        #[cfg(not(feature = "async"))]
+        block_on(test);
+    }
+
+    /// Both ends of a connection: (client, server).
+    #[cfg(feature = "async")]
+    fn socket_pair() -> (impl Socket, impl Socket) {
+        tokio::net::UnixStream::pair().unwrap()
+    }
+
+    #[cfg(not(feature = "async"))]
+    fn socket_pair() -> (impl Socket, impl Socket) {
+        UnixStream::pair().unwrap()
+    }
+
+    /// Everything the server sends until it closes the connection.
+    async fn read_to_end(client: &mut impl Socket) -> String {
+        let mut response = Vec::with_capacity(64 * 1024);
+        while client
+            .read_into(&mut response, deadline(std::time::Duration::from_secs(5)))
+            .await
+            .unwrap()
+            > 0
+        {
+            response.reserve(64 * 1024);
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    /// Stand-in for the main thread: answer each request with `answer`.
+    fn answering(answer: fn(HttpRequest) -> String) -> std::sync::mpsc::Sender<Wake> {
+        let (jobs, requests) = std::sync::mpsc::channel::<Wake>();
+        std::thread::spawn(move || {
+            for wake in requests.iter() {
+                if let Wake::Request(request, reply_to) = wake {
+                    let _ = reply_to.send(HttpReply::new(200, answer(request)));
+                }
+            }
+        });
+        jobs
+    }
+
+    fn limits(max_body: usize) -> ConnectionLimits {
+        ConnectionLimits {
+            header_timeout: std::time::Duration::from_secs(5),
+            read_timeout: std::time::Duration::from_secs(5),
+            max_body,
+        }
+    }
+
+    #[test]
+    fn test_connection_reads_split_request_and_replies() {
+        let jobs = answering(|request| request.path);
+        run(async move {
+            let (mut client, server) = socket_pair();
+            let peer = PeerInfo::Tcp {
+                client_addr: "127.0.0.1".into(),
+            };
+            spawn(serve_connection(server, peer, limits(usize::MAX), jobs));
+            let request = b"POST /hook?event=Stop HTTP/1.1\r\nContent-Length: 7\r\n\r\n{\"a\":1}";
+            // Dribbled in: the connection waits for the whole body
+            for piece in request.chunks(9) {
+                client.send(piece, b"").await.unwrap();
+                sleep(std::time::Duration::from_millis(2)).await;
+            }
+            client.shutdown_write().await; // keep-alive: EOF ends the connection
+            let response = read_to_end(&mut client).await;
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            assert!(
+                response.ends_with("\r\n\r\n/hook?event=Stop"),
+                "{}",
+                response
+            );
+        });
+    }
+
+    #[test]
+    fn test_connection_answers_pipelined_requests_in_order() {
+        let jobs = answering(|request| request.body);
+        run(async move {
+            let (mut client, server) = socket_pair();
+            spawn(serve_connection(
+                server,
+                PeerInfo::Unknown,
+                limits(usize::MAX),
+                jobs,
+            ));
+            // Three requests in one write; the last asks to close
+            let mut pipelined = String::new();
+            for (i, connection) in ["keep-alive", "keep-alive", "close"].iter().enumerate() {
+                let body = format!(r#"{{"n":{}}}"#, i);
+                pipelined += &format!(
+                    "POST /hook?event=Stop HTTP/1.1\r\nConnection: {}\r\nContent-Length: {}\r\n\r\n{}",
+                    connection,
+                    body.len(),
+                    body
+                );
+            }
+            client.send(pipelined.as_bytes(), b"").await.unwrap();
+            // No shutdown from our side: "Connection: close" ends it
+            let response = read_to_end(&mut client).await;
+            let bodies: Vec<&str> = response
+                .split("HTTP/1.1 200 OK\r\n")
+                .skip(1)
+                .map(|reply| reply.split_once("\r\n\r\n").unwrap().1)
+                .collect();
+            assert_eq!(bodies, [r#"{"n":0}"#, r#"{"n":1}"#, r#"{"n":2}"#]);
+        });
+    }
+
+    #[test]
+    fn test_connection_answers_expect_100_continue_and_times_out() {
+        let jobs = answering(|request| request.body);
+        run(async move {
+            let (mut client, server) = socket_pair();
+            spawn(serve_connection(
+                server,
+                PeerInfo::Unknown,
+                limits(1024),
+                jobs.clone(),
+            ));
+            // Headers only: the body is held back until the server says so
+            client
+                .send(b"POST /hook?event=Stop HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 7\r\nConnection: close\r\n\r\n", b"")
+                .await
+                .unwrap();
+            let mut interim = Vec::with_capacity(25);
+            while interim.len() < 25 {
+                client.read_into(&mut interim, None).await.unwrap();
+            }
+            assert_eq!(interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+            client.send(br#"{"a":1}"#, b"").await.unwrap();
+            let response = read_to_end(&mut client).await;
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            assert!(response.ends_with(r#"{"a":1}"#), "{}", response);
+
+            // A body that never comes: a 408 once --read-timeout is up
+            let (mut client, server) = socket_pair();
+            let impatient = ConnectionLimits {
+                read_timeout: std::time::Duration::from_millis(50),
+                ..limits(1024)
+            };
+            spawn(serve_connection(server, PeerInfo::Unknown, impatient, jobs));
+            client
+                .send(
+                    b"POST /hook?event=Stop HTTP/1.1\r\nContent-Length: 7\r\n\r\n{",
+                    b"",
+                )
+                .await
+                .unwrap();
+            let response = read_to_end(&mut client).await;
+            assert!(response.starts_with("HTTP/1.1 408 "), "{}", response);
+        });
+    }
+
+    #[test]
+    fn test_connection_slots_cap_and_free() {
+        let slots: ConnectionSlots = Some(Arc::new(std::sync::atomic::AtomicUsize::new(2)));
+        let mut refused = 0;
+        let first = take_slot(&slots, &mut refused).unwrap();
+        let second = take_slot(&slots, &mut refused).unwrap();
+        assert!(first.is_some() && second.is_some());
+        assert!(take_slot(&slots, &mut refused).is_none());
+        assert_eq!(refused, 1);
+        drop(first); // a closed connection frees its slot
+        assert!(take_slot(&slots, &mut refused).is_some());
+        assert!(matches!(take_slot(&None, &mut refused), Some(None))); // no cap
+        assert_eq!(
+            DEFAULT_MAX_CONNECTIONS.is_some(),
+            !cfg!(feature = "async"),
+            "a thread per connection is always capped"
+        );
+    }
+}
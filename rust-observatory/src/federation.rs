@@ -0,0 +1,236 @@
+//! `federate`: one observatory following the output of several others.
+
+use super::*;
+
+// === FEDERATION ===
+// `federate` follows several observatories (one reader thread each,
+// reconnecting when an upstream restarts): the JSONL output socket of a local
+// one, or `GET /events` of a TCP one, polled. Every event is labelled with
+// `_source` and funnelled through the main thread's wake channel into the
+// normal publish path - so the merged stream gets pretty modes, --repl, an
+// output socket of its own, and with --listen the query API, for free.
+
+const FEDERATE_RECONNECT: std::time::Duration = std::time::Duration::from_secs(2);
+const FEDERATE_DEDUPE_WINDOW: usize = 100_000;
+const FEDERATE_POLL: std::time::Duration = std::time::Duration::from_millis(500);
+const FEDERATE_POLL_LIMIT: usize = 1000; // events per GET /events
+
+/// Follow one upstream, sending its events as (source name, line) wakes.
+fn follow_source(
+    name: String,
+    path: String,
+    jobs: std::sync::mpsc::Sender<Wake>,
+    running: Arc<AtomicBool>,
+) {
+    use std::io::BufRead;
+
+    if path.starts_with("http://") {
+        return poll_source(name, path, jobs, running);
+    }
+    while running.load(Ordering::SeqCst) {
+        if let Ok(stream) = UnixStream::connect(&path) {
+            tracing::info!(source = %name, %path, "Federate: connected");
+            for line in std::io::BufReader::new(stream).lines() {
+                let Ok(line) = line else { break };
+                if jobs.send(Wake::Federated(name.clone(), line)).is_err() {
+                    return;
+                }
+            }
+            tracing::warn!(source = %name, "Federate: disconnected, retrying");
+        }
+        std::thread::sleep(FEDERATE_RECONNECT);
+    }
+}
+
+/// Poll a TCP upstream's `GET /events?after=<last _id>` every FEDERATE_POLL.
+///
+/// The first poll backfills what the upstream's history holds. An upstream
+/// that publishes more than FEDERATE_POLL_LIMIT events between two polls
+/// answers with the newest ones only, so the rest are missed (and warned
+/// about): a poll is not a stream.
+fn poll_source(
+    name: String,
+    url: String,
+    jobs: std::sync::mpsc::Sender<Wake>,
+    running: Arc<AtomicBool>,
+) {
+    let base = url.trim_end_matches('/');
+    let mut after: Option<String> = None;
+    let mut connected = false;
+    while running.load(Ordering::SeqCst) {
+        let mut query = format!("{}/events?limit={}", base, FEDERATE_POLL_LIMIT);
+        if let Some(ref id) = after {
+            query.push_str(&format!("&after={}", id));
+        }
+        let events = match http_get_json(&query) {
+            Ok((200, body)) => serde_json::from_str::<Vec<Value>>(&body).map_err(|e| e.to_string()),
+            Ok((status, _)) => Err(format!("GET /events answered {}", status)),
+            Err(e) => Err(e.to_string()),
+        };
+        let events = match events {
+            Ok(events) => events,
+            Err(error) => {
+                if std::mem::take(&mut connected) {
+                    tracing::warn!(source = %name, %error, "Federate: disconnected, retrying");
+                }
+                std::thread::sleep(FEDERATE_RECONNECT);
+                continue;
+            }
+        };
+        if !std::mem::replace(&mut connected, true) {
+            tracing::info!(source = %name, %url, "Federate: connected");
+        }
+        if after.is_some() && events.len() >= FEDERATE_POLL_LIMIT {
+            tracing::warn!(source = %name, "Federate: upstream outpaced the poll, events may be missing");
+        }
+        for event in events {
+            if let Some(id) = event.get("_id").and_then(Value::as_str) {
+                after = Some(id.to_string());
+            }
+            if jobs
+                .send(Wake::Federated(name.clone(), event.to_string()))
+                .is_err()
+            {
+                return;
+            }
+        }
+        std::thread::sleep(FEDERATE_POLL);
+    }
+}
+
+/// Drops events already seen, keyed on `_id`, or on (source name, `_seq`)
+/// for upstreams that don't assign IDs.
+///
+/// Events with neither can't be identified and always pass through.
+/// Memory is bounded by forgetting the oldest keys past the window.
+pub(crate) struct Deduper {
+    seen: HashSet<String>,
+    order: VecDeque<String>,
+    window: usize,
+}
+
+impl Deduper {
+    pub(crate) fn new(window: usize) -> Self {
+        Self {
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+            window,
+        }
+    }
+
+    /// True the first time an event's key is seen.
+    pub(crate) fn first_sighting(&mut self, source: &str, event: &Value) -> bool {
+        let key = if let Some(id) = event.get("_id").and_then(Value::as_str) {
+            id.to_string()
+        } else if let Some(seq) = event.get("_seq").and_then(Value::as_u64) {
+            format!("{}#{}", source, seq)
+        } else {
+            return true;
+        };
+        if !self.seen.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.window {
+            if let Some(old) = self.order.pop_front() {
+                self.seen.remove(&old);
+            }
+        }
+        true
+    }
+}
+
+/// Main loop of the `federate` subcommand. With --listen, `requests` also
+/// carries the query API's requests; hooks aren't taken (405).
+pub(crate) fn run_federation(
+    sources: Vec<(String, String)>,
+    ctx: &mut ServerContext,
+    running: &Arc<AtomicBool>,
+    (jobs, requests): (
+        std::sync::mpsc::Sender<Wake>,
+        std::sync::mpsc::Receiver<Wake>,
+    ),
+    poll_interval: std::time::Duration,
+) {
+    use std::sync::mpsc::RecvTimeoutError;
+
+    for (name, path) in sources {
+        let (jobs, running) = (jobs.clone(), running.clone());
+        std::thread::spawn(move || follow_source(name, path, jobs, running));
+    }
+    drop(jobs);
+
+    let mut deduper = Deduper::new(FEDERATE_DEDUPE_WINDOW);
+    let mut warned: HashSet<String> = HashSet::new();
+    while running.load(Ordering::SeqCst) {
+        // Upstream lines wake us immediately; the timeout only paces
+        // the housekeeping and the shutdown check
+        match requests.recv_timeout(poll_interval) {
+            Ok(Wake::Federated(source, line)) => {
+                let mut event: Value = match serde_json::from_str(&line) {
+                    Ok(v @ Value::Object(_)) => v,
+                    _ => {
+                        if warned.insert(source.clone()) {
+                            tracing::warn!(
+                                %source,
+                                "Federate: not JSONL (run it without --pretty-*)"
+                            );
+                        }
+                        continue;
+                    }
+                };
+                // Upstream heartbeats vouch for their own pipe; ours covers this one
+                if event_name(&event).as_deref() == Some(HEARTBEAT_EVENT)
+                    || !deduper.first_sighting(&source, &event)
+                {
+                    continue;
+                }
+                event["_source"] = Value::String(source);
+                ctx.publish_federated(event);
+            }
+            Ok(Wake::Request(request, reply_to)) => {
+                let (method, path) = (request.method.clone(), request.path.clone());
+                let reply = if method == "POST" {
+                    HttpReply {
+                        headers: vec![("Allow", "GET, HEAD".to_string())],
+                        ..HttpReply::error(
+                            405,
+                            "method_not_allowed",
+                            "federate answers queries, not hooks",
+                        )
+                    }
+                } else {
+                    handle_request(request, ctx)
+                };
+                log_reply(&method, &path, &reply);
+                let _ = reply_to.send(reply);
+            }
+            Ok(Wake::Refused(status)) => ctx.count_refusal(status),
+            Ok(Wake::Answered(method, elapsed)) => ctx.latency.record(&method, elapsed),
+            Ok(Wake::Clicked(..) | Wake::Consulted(..)) => {} // nothing is parked here
+            Ok(Wake::Shutdown) => break,
+            Err(RecvTimeoutError::Timeout) => ctx.tick(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deduper_by_source_and_seq() {
+        let mut deduper = Deduper::new(2);
+        let a1 = serde_json::json!({"_seq": 1});
+        assert!(deduper.first_sighting("a", &a1));
+        assert!(!deduper.first_sighting("a", &a1)); // replayed after a reconnect
+        assert!(deduper.first_sighting("b", &a1)); // another upstream's 1
+                                                   // No _seq: can't dedupe, always passes
+        assert!(deduper.first_sighting("a", &serde_json::json!({})));
+        assert!(deduper.first_sighting("a", &serde_json::json!({})));
+        // Window of 2 forgot the oldest key
+        deduper.first_sighting("c", &a1);
+        assert!(deduper.first_sighting("a", &a1));
+    }
+}
@@ -0,0 +1,7 @@
+//! Library side of rust-observatory: the pieces other Rust programs can reuse.
+//!
+//! The server itself is the binary (main.rs and its subsystem modules);
+//! only the client lives here so hook helpers can depend on it.
+
+#[cfg(feature = "client")]
+pub mod client;
@@ -0,0 +1,636 @@
+//! Reports written from captured events: the --html-report file and the
+//! scheduled --report summaries.
+
+use super::*;
+
+// === HTML REPORT ===
+// One standalone file: a summary table rendered server-side plus every event
+// embedded as JSON, with a few lines of JS for filtering and collapsible
+// payloads. Opens from disk in any browser - no server, no CDN.
+
+const HTML_REPORT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Claude Code hooks report</title>
+<style>
+body { font: 14px/1.4 system-ui, sans-serif; margin: 2em; max-width: 72em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+td, th { padding: 2px 12px 2px 0; text-align: left; }
+td.n { text-align: right; }
+details { border-bottom: 1px solid #ddd; padding: 4px 0; }
+summary { cursor: pointer; }
+summary code { color: #666; }
+pre { background: #f6f6f6; padding: 8px; overflow-x: auto; }
+input { font: inherit; width: 30em; margin: 1em 0; }
+</style>
+</head>
+<body>
+<h1>Claude Code hooks report</h1>
+{{SUMMARY}}
+<input id="filter" placeholder="Filter events (matches any text in the event)">
+<div id="events"></div>
+<script type="application/json" id="data">{{EVENTS}}</script>
+<script>
+const events = JSON.parse(document.getElementById("data").textContent);
+const list = document.getElementById("events");
+function render(filter) {
+  list.replaceChildren();
+  for (const e of events) {
+    const json = JSON.stringify(e, null, 2);
+    if (filter && !json.toLowerCase().includes(filter)) continue;
+    const d = document.createElement("details");
+    const s = document.createElement("summary");
+    const ts = document.createElement("code");
+    ts.textContent = e._ts || "";
+    s.append(ts, " " + (e._event || "?") + (e.tool_name ? " · " + e.tool_name : ""));
+    const pre = document.createElement("pre");
+    pre.textContent = json;
+    d.append(s, pre);
+    list.append(d);
+  }
+}
+document.getElementById("filter").addEventListener("input", ev => render(ev.target.value.toLowerCase()));
+render("");
+</script>
+</body>
+</html>
+"#;
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Count events by a key (e.g. event name, tool), most frequent first.
+pub(crate) fn count_by(
+    events: &[Value],
+    key: impl Fn(&Value) -> Option<String>,
+) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for k in events.iter().filter_map(key) {
+        count_into(&mut counts, k);
+    }
+    counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+    counts
+}
+
+/// Count one more `key`, keeping first-seen order (count_by sorts after).
+fn count_into(counts: &mut Vec<(String, usize)>, key: String) {
+    match counts.iter_mut().find(|(existing, _)| *existing == key) {
+        Some((_, n)) => *n += 1,
+        None => counts.push((key, 1)),
+    }
+}
+
+pub(crate) fn event_name(event: &Value) -> Option<String> {
+    Some(event.get("_event").map(value_text).unwrap_or_default())
+}
+
+/// The hook events Claude Code sends. Counters keyed by `_event`, which
+/// the sender chooses, count any other name as "other" (as RequestLatency
+/// does with methods), so made-up names can't grow them without bound.
+const HOOK_EVENTS: [&str; 12] = [
+    "SessionStart",
+    "UserPromptSubmit",
+    "PreToolUse",
+    "PostToolUse",
+    "PostToolUseFailure",
+    "PermissionRequest",
+    "Notification",
+    "Stop",
+    "SubagentStart",
+    "SubagentStop",
+    "PreCompact",
+    "SessionEnd",
+];
+
+/// `name` as a counter key: itself for a hook event, else "other".
+pub(crate) fn event_label(name: &str) -> &'static str {
+    HOOK_EVENTS
+        .into_iter()
+        .find(|&known| known == name)
+        .unwrap_or("other")
+}
+
+/// Distinct keys a counter map with an open-ended key (tool, client,
+/// session) tracks before it counts new ones as "other".
+pub(crate) const MAX_LABELS: usize = 1024;
+
+/// `key` as a counter key: itself if the map already has it (`seen`) or
+/// has room for it (tracks fewer than MAX_LABELS keys), else "other".
+pub(crate) fn capped_label(key: String, seen: bool, tracked: usize) -> String {
+    if seen || tracked < MAX_LABELS {
+        key
+    } else {
+        "other".into()
+    }
+}
+
+pub(crate) fn tool_name(event: &Value) -> Option<String> {
+    event
+        .get("tool_name")
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+pub(crate) fn session_count(events: &[Value]) -> usize {
+    let ids: HashSet<&str> = events
+        .iter()
+        .filter_map(|e| e.get("session_id").and_then(Value::as_str))
+        .collect();
+    ids.len()
+}
+
+/// Server-side summary: time range, sessions, and counts per event and
+/// tool, gathered one event at a time so --html-report can read its events
+/// back from the spool file.
+#[derive(Default)]
+struct HtmlSummary {
+    events: usize,
+    first_ts: String,
+    last_ts: String,
+    sessions: HashSet<String>,
+    by_event: Vec<(String, usize)>,
+    by_tool: Vec<(String, usize)>,
+}
+
+impl HtmlSummary {
+    fn add(&mut self, event: &Value) {
+        let ts = event.get("_ts").map(value_text).unwrap_or_default();
+        if self.events == 0 {
+            self.first_ts = ts.clone();
+        }
+        self.last_ts = ts;
+        self.events += 1;
+        if let Some(id) = event.get("session_id").and_then(Value::as_str) {
+            if !self.sessions.contains(id) {
+                self.sessions.insert(id.to_string());
+            }
+        }
+        if let Some(name) = event_name(event) {
+            count_into(&mut self.by_event, name);
+        }
+        if let Some(tool) = tool_name(event) {
+            count_into(&mut self.by_tool, tool);
+        }
+    }
+
+    fn render(mut self) -> String {
+        let table = |title: &str, counts: &[(String, usize)]| {
+            let rows: String = counts
+                .iter()
+                .map(|(k, n)| {
+                    format!(
+                        "<tr><td>{}</td><td class=\"n\">{}</td></tr>",
+                        html_escape(k),
+                        n
+                    )
+                })
+                .collect();
+            format!(
+                "<table><tr><th>{}</th><th>Count</th></tr>{}</table>\n",
+                html_escape(title),
+                rows
+            )
+        };
+        self.by_event.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+        self.by_tool.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+        format!(
+            "<p>{} events from {} to {}, {} sessions</p>\n{}{}",
+            self.events,
+            html_escape(&self.first_ts),
+            html_escape(&self.last_ts),
+            self.sessions.len(),
+            table("Event", &self.by_event),
+            table("Tool", &self.by_tool)
+        )
+    }
+}
+
+fn render_html_report(events: &[Value]) -> String {
+    let mut summary = HtmlSummary::default();
+    events.iter().for_each(|event| summary.add(event));
+    let mut html = Vec::new();
+    write_html_report(&mut html, summary, events.iter().map(|e| Ok(e.to_string())))
+        .expect("writing to memory");
+    String::from_utf8(html).unwrap()
+}
+
+/// Write the report: the template with the summary filled in, and the
+/// events (one compact JSON object each) as a JSON array.
+fn write_html_report(
+    out: &mut impl Write,
+    summary: HtmlSummary,
+    events: impl Iterator<Item = std::io::Result<String>>,
+) -> std::io::Result<()> {
+    // Both placeholders are found in the template before anything is filled
+    // in: text from the events is never searched for a placeholder
+    let (head, rest) = HTML_REPORT_TEMPLATE.split_once("{{SUMMARY}}").unwrap();
+    let (middle, tail) = rest.split_once("{{EVENTS}}").unwrap();
+    out.write_all(head.as_bytes())?;
+    out.write_all(summary.render().as_bytes())?;
+    out.write_all(middle.as_bytes())?;
+    out.write_all(b"[")?;
+    for (i, event) in events.enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        // No "<" at all in the data block, so nothing in a string ("</script>",
+        // "<!--") can end it early; JSON.parse reads \u003c back as "<"
+        out.write_all(event?.replace('<', "\\u003c").as_bytes())?;
+    }
+    out.write_all(b"]")?;
+    out.write_all(tail.as_bytes())
+}
+
+/// --html-report: each event is appended to a spool file next to the report
+/// (PATH.spool.jsonl) as it is published, and read back at shutdown to
+/// write the report, so a long-running server doesn't hold every event in
+/// memory.
+pub(crate) struct HtmlReportSpool {
+    pub(crate) path: String,
+    spool_path: String,
+    spool: std::io::BufWriter<std::fs::File>,
+    errors: u64,
+}
+
+impl HtmlReportSpool {
+    pub(crate) fn create(path: &str) -> std::io::Result<Self> {
+        let spool_path = format!("{}.spool.jsonl", path);
+        let spool = std::io::BufWriter::new(std::fs::File::create(&spool_path)?);
+        Ok(Self {
+            path: path.to_string(),
+            spool_path,
+            spool,
+            errors: 0,
+        })
+    }
+
+    pub(crate) fn add(&mut self, line: &str) {
+        if let Err(e) = writeln!(self.spool, "{}", line) {
+            self.errors += 1;
+            if self.errors.is_power_of_two() {
+                tracing::error!(path = %self.spool_path, error = %e, errors = self.errors, "Cannot write HTML report spool");
+            }
+        }
+    }
+
+    /// Write the report from the spool, then delete the spool. Returns the
+    /// number of events in the report; a line cut short by a failed write
+    /// is left out.
+    pub(crate) fn finish(mut self) -> std::io::Result<usize> {
+        self.spool.flush()?;
+        let lines = || -> std::io::Result<_> {
+            let spool = std::io::BufReader::new(std::fs::File::open(&self.spool_path)?);
+            Ok(std::io::BufRead::lines(spool).filter(|line| {
+                line.as_ref().map_or(true, |line| {
+                    serde_json::from_str::<serde::de::IgnoredAny>(line).is_ok()
+                })
+            }))
+        };
+        let mut summary = HtmlSummary::default();
+        for line in lines()? {
+            summary.add(&serde_json::from_str(&line?)?);
+        }
+        let events = summary.events;
+        let mut out = std::io::BufWriter::new(std::fs::File::create(&self.path)?);
+        write_html_report(&mut out, summary, lines()?)?;
+        out.flush()?;
+        std::fs::remove_file(&self.spool_path)?;
+        Ok(events)
+    }
+}
+
+// === SCHEDULED REPORTS ===
+// A background thread wakes at each scheduled time, takes the events
+// collected since the previous report, and writes one summary file. Like the
+// HTML report, it needs no store or cron - only the events seen while running.
+
+#[derive(Clone, Copy)]
+pub(crate) enum ReportSchedule {
+    Daily(chrono::NaiveTime),
+    Every(std::time::Duration),
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum ReportFormat {
+    Markdown,
+    Json,
+    Html,
+}
+
+pub(crate) fn parse_report_schedule(value: &str) -> Result<ReportSchedule, String> {
+    match value.split_once('=') {
+        Some(("daily", time)) => chrono::NaiveTime::parse_from_str(time, "%H:%M")
+            .map(ReportSchedule::Daily)
+            .map_err(|_| format!("invalid time '{}' (expected HH:MM)", time)),
+        Some(("every", duration)) => {
+            let every = parse_interval(duration)?;
+            // next_after adds it to a date, which has to stay representable
+            chrono::Duration::from_std(every)
+                .ok()
+                .and_then(|d| chrono::Local::now().checked_add_signed(d))
+                .map(|_| ReportSchedule::Every(every))
+                .ok_or_else(|| format!("interval '{}' is too large", duration))
+        }
+        _ => Err("expected daily=HH:MM or every=DURATION".into()),
+    }
+}
+
+impl ReportSchedule {
+    fn next_after(&self, now: chrono::DateTime<chrono::Local>) -> chrono::DateTime<chrono::Local> {
+        use chrono::TimeZone;
+        match *self {
+            ReportSchedule::Every(d) => now + chrono::Duration::from_std(d).unwrap(),
+            ReportSchedule::Daily(time) => {
+                // earliest(): a DST gap skips that day's slot, a fold takes the first
+                let at = |date: chrono::NaiveDate| {
+                    chrono::Local
+                        .from_local_datetime(&date.and_time(time))
+                        .earliest()
+                };
+                let today = now.date_naive();
+                at(today)
+                    .filter(|t| *t > now)
+                    .or_else(|| at(today.succ_opt()?))
+                    .unwrap_or(now + chrono::Duration::days(1))
+            }
+        }
+    }
+}
+
+pub(crate) fn is_error_event(event: &Value) -> bool {
+    event.get("_event").and_then(Value::as_str) == Some("PostToolUseFailure")
+        || event.get("error").is_some()
+}
+
+/// Summarize a reporting period: sessions, errors, and tool mix.
+/// Hook payloads carry no token or cost data, so there's nothing to total there.
+pub(crate) fn build_report(events: &[Value], start: &str, end: &str) -> Value {
+    let session_of = |e: &Value| {
+        e.get("session_id")
+            .and_then(Value::as_str)
+            .map(String::from)
+    };
+    let sessions: Vec<Value> = count_by(events, session_of)
+        .into_iter()
+        .map(|(id, count)| {
+            let mine: Vec<&Value> = events
+                .iter()
+                .filter(|e| session_of(e).as_deref() == Some(&id))
+                .collect();
+            let ts =
+                |e: Option<&&Value>| e.and_then(|e| e.get("_ts")).cloned().unwrap_or(Value::Null);
+            let prompts = mine
+                .iter()
+                .filter(|e| e["_event"] == "UserPromptSubmit")
+                .count();
+            serde_json::json!({
+                "session_id": id, "events": count, "prompts": prompts,
+                "first": ts(mine.first()), "last": ts(mine.last()),
+            })
+        })
+        .collect();
+    let errors: Vec<Value> = events
+        .iter()
+        .filter(|e| is_error_event(e))
+        .map(|e| {
+            let message: String = e.get("error").map(value_text).unwrap_or_default().chars().take(200).collect();
+            serde_json::json!({"ts": e["_ts"], "session_id": e["session_id"], "tool": e["tool_name"], "error": message})
+        })
+        .collect();
+    let calls: Vec<Value> = events
+        .iter()
+        .filter(|e| e["_event"] == "PreToolUse")
+        .cloned()
+        .collect();
+    let tools: serde_json::Map<String, Value> = count_by(&calls, tool_name)
+        .into_iter()
+        .map(|(tool, n)| (tool, n.into()))
+        .collect();
+    serde_json::json!({
+        "period_start": start,
+        "period_end": end,
+        "events": events.len(),
+        "sessions": sessions,
+        "errors": errors,
+        "tools": tools,
+    })
+}
+
+fn render_report_markdown(report: &Value) -> String {
+    let text = |v: &Value| value_text(v);
+    let empty = Vec::new();
+    let list = |key: &str| report[key].as_array().unwrap_or(&empty);
+    let mut out = format!(
+        "# Claude Code activity report\n\n{} – {} · {} events · {} sessions · {} errors\n",
+        text(&report["period_start"]),
+        text(&report["period_end"]),
+        report["events"],
+        list("sessions").len(),
+        list("errors").len()
+    );
+
+    out.push_str(
+        "\n## Sessions\n\n| Session | Events | Prompts | First | Last |\n|---|---|---|---|---|\n",
+    );
+    for s in list("sessions") {
+        out.push_str(&format!(
+            "| `{}` | {} | {} | {} | {} |\n",
+            text(&s["session_id"]),
+            s["events"],
+            s["prompts"],
+            text(&s["first"]),
+            text(&s["last"])
+        ));
+    }
+
+    out.push_str("\n## Tool mix\n\n| Tool | Calls |\n|---|---|\n");
+    if let Some(tools) = report["tools"].as_object() {
+        // Map keys come out sorted by name; show the busiest tools first
+        let mut tools: Vec<_> = tools.iter().collect();
+        tools.sort_by_key(|(_, n)| std::cmp::Reverse(n.as_u64()));
+        for (tool, n) in tools {
+            out.push_str(&format!("| {} | {} |\n", tool, n));
+        }
+    }
+
+    out.push_str("\n## Errors\n\n");
+    if list("errors").is_empty() {
+        out.push_str("None.\n");
+    }
+    for e in list("errors") {
+        out.push_str(&format!(
+            "- `{}` {} (session `{}`): {}\n",
+            text(&e["ts"]),
+            text(&e["tool"]),
+            text(&e["session_id"]),
+            text(&e["error"]).replace('\n', " ")
+        ));
+    }
+    out
+}
+
+fn rfc3339(t: chrono::DateTime<chrono::Local>) -> String {
+    t.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+}
+
+fn write_report(
+    dir: &str,
+    format: ReportFormat,
+    events: &[Value],
+    start: chrono::DateTime<chrono::Local>,
+    end: chrono::DateTime<chrono::Local>,
+) -> std::io::Result<std::path::PathBuf> {
+    let report = build_report(events, &rfc3339(start), &rfc3339(end));
+    let (extension, body) = match format {
+        ReportFormat::Markdown => ("md", render_report_markdown(&report)),
+        ReportFormat::Json => ("json", serde_json::to_string_pretty(&report).unwrap()),
+        ReportFormat::Html => ("html", render_html_report(events)),
+    };
+    let path = std::path::Path::new(dir).join(format!(
+        "report-{}.{}",
+        end.format("%Y%m%d-%H%M%S"),
+        extension
+    ));
+    std::fs::write(&path, body)?;
+    Ok(path)
+}
+
+pub(crate) fn run_report_scheduler(
+    buffer: Arc<Mutex<Vec<Value>>>,
+    schedule: ReportSchedule,
+    format: ReportFormat,
+    dir: String,
+) {
+    let mut period_start = chrono::Local::now();
+    loop {
+        let next = schedule.next_after(chrono::Local::now());
+        if let Ok(wait) = (next - chrono::Local::now()).to_std() {
+            std::thread::sleep(wait);
+        }
+        let events = std::mem::take(&mut *buffer.lock().unwrap());
+        let now = chrono::Local::now();
+        match write_report(&dir, format, &events, period_start, now) {
+            Ok(path) => eprintln!("Report: {} ({} events)", path.display(), events.len()),
+            Err(e) => eprintln!("Error: Cannot write report in {}: {}", dir, e),
+        }
+        period_start = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_report_embeds_events_safely() {
+        let events = vec![
+            serde_json::json!({"_event": "PreToolUse", "_ts": "t1", "tool_name": "Bash", "session_id": "s1",
+                               "tool_input": {"command": "echo '</script><b>'"}}),
+            serde_json::json!({"_event": "PreToolUse", "_ts": "t2", "tool_name": "Bash", "session_id": "s2"}),
+        ];
+        let html = render_html_report(&events);
+        assert!(html.contains("2 events from t1 to t2, 2 sessions"));
+        assert!(html.contains("<tr><td>Bash</td><td class=\"n\">2</td></tr>"));
+        // The payload must not close the data <script> block early
+        assert_eq!(html.matches("</script>").count(), 2);
+        assert!(html.contains("\\u003c/script>"));
+    }
+
+    #[test]
+    fn test_html_report_spool_matches_in_memory_report() {
+        let path = std::env::temp_dir().join(format!("obs-report-{}.html", std::process::id()));
+        let path = path.to_str().unwrap();
+        let events = vec![
+            serde_json::json!({"_event": "PreToolUse", "_ts": "t1", "tool_name": "Bash", "session_id": "s1",
+                               "tool_input": {"command": "echo '</script>'"}}),
+            serde_json::json!({"_event": "Stop", "_ts": "t2", "session_id": "s1"}),
+        ];
+        let mut spool = HtmlReportSpool::create(path).unwrap();
+        for event in &events {
+            spool.add(&event.to_string());
+        }
+        // A line cut short by a failed write is left out
+        spool.add("{\"_event\":");
+        let spool_path = spool.spool_path.clone();
+        assert_eq!(spool.finish().unwrap(), 2);
+        assert!(!std::path::Path::new(&spool_path).exists());
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            render_html_report(&events)
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_html_report_keeps_hostile_names_out_of_markup() {
+        let hostile = "{{EVENTS}}<img src=x onerror=alert(1)>";
+        let events = vec![
+            serde_json::json!({"_event": hostile, "_ts": "<b>", "tool_name": hostile,
+                               "tool_input": {"command": "<!-- </script>"}}),
+        ];
+        let html = render_html_report(&events);
+        // The placeholder in the name is left as text, not filled with the events
+        assert_eq!(html.matches("<script").count(), 2);
+        assert!(!html.contains("<img") && !html.contains("<b>"));
+        assert!(html.contains("<td>{{EVENTS}}&lt;img src=x onerror=alert(1)&gt;</td>"));
+        // The data block holds no "<" and still parses back to the events
+        let start = html.find("id=\"data\">").unwrap() + 10;
+        let data = &html[start..start + html[start..].find("</script>").unwrap()];
+        assert!(!data.contains('<'));
+        assert_eq!(serde_json::from_str::<Vec<Value>>(data).unwrap(), events);
+    }
+
+    #[test]
+    fn test_report_schedule_next_after() {
+        use chrono::TimeZone;
+        let now = chrono::Local
+            .with_ymd_and_hms(2025, 3, 10, 10, 30, 0)
+            .unwrap();
+        let daily = parse_report_schedule("daily=09:00").unwrap();
+        assert_eq!(
+            daily.next_after(now),
+            chrono::Local
+                .with_ymd_and_hms(2025, 3, 11, 9, 0, 0)
+                .unwrap()
+        );
+        let later = parse_report_schedule("daily=18:15").unwrap();
+        assert_eq!(
+            later.next_after(now),
+            chrono::Local
+                .with_ymd_and_hms(2025, 3, 10, 18, 15, 0)
+                .unwrap()
+        );
+        let every = parse_report_schedule("every=6h").unwrap();
+        assert_eq!(every.next_after(now), now + chrono::Duration::hours(6));
+        assert!(parse_report_schedule("daily=9am").is_err());
+        assert!(parse_report_schedule("weekly=mon").is_err());
+        assert!(parse_report_schedule("every=99999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_build_report_summarizes_period() {
+        let events = vec![
+            serde_json::json!({"_event": "UserPromptSubmit", "_ts": "t1", "session_id": "s1", "prompt": "fix it"}),
+            serde_json::json!({"_event": "PreToolUse", "_ts": "t2", "session_id": "s1", "tool_name": "Bash"}),
+            serde_json::json!({"_event": "PostToolUseFailure", "_ts": "t3", "session_id": "s1", "tool_name": "Bash", "error": "exit 1"}),
+            serde_json::json!({"_event": "PreToolUse", "_ts": "t4", "session_id": "s2", "tool_name": "Read"}),
+        ];
+        let report = build_report(&events, "start", "end");
+        assert_eq!(report["events"], 4);
+        assert_eq!(report["sessions"][0]["session_id"], "s1");
+        assert_eq!(report["sessions"][0]["prompts"], 1);
+        assert_eq!(report["sessions"][0]["last"], "t3");
+        assert_eq!(report["tools"], serde_json::json!({"Bash": 1, "Read": 1}));
+        assert_eq!(report["errors"][0]["error"], "exit 1");
+
+        let md = render_report_markdown(&report);
+        assert!(md.contains("start – end · 4 events · 2 sessions · 1 errors"));
+        assert!(md.contains("| `s1` | 3 | 1 | t1 | t3 |"));
+        assert!(md.contains("- `t3` Bash (session `s1`): exit 1"));
+    }
+}
@@ -0,0 +1,243 @@
+//! The full-text index behind GET /search and `query`: an FTS5 table in the
+//! SQLite store.
+
+use super::*;
+
+// === FULL-TEXT SEARCH ===
+// Every store insert also adds the event's text to events_fts (rowid =
+// events.id), so the index lives on disk next to the events, covers all of
+// them, and `query` only opens it. SQLite ranks matches with BM25 (the same
+// scoring as Lucene/tantivy) and cuts the snippets.
+
+#[cfg(feature = "sqlite")]
+const SNIPPET_TOKENS: usize = 16;
+
+/// A ranked search hit.
+pub(crate) struct SearchHit {
+    score: f64,
+    snippet: String,
+    event: Value,
+}
+
+/// Lowercased alphanumeric tokens of at least two characters.
+#[cfg(feature = "sqlite")]
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|t| t.chars().count() >= 2)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// All string values in an event (recursively), newline-separated.
+/// Our `_` metadata and opaque ids are skipped - they only add noise.
+#[cfg(feature = "sqlite")]
+pub(crate) fn searchable_text(value: &Value) -> String {
+    fn collect(value: &Value, out: &mut String) {
+        match value {
+            Value::String(s) => {
+                out.push_str(s);
+                out.push('\n');
+            }
+            Value::Array(items) => items.iter().for_each(|v| collect(v, out)),
+            Value::Object(map) => map.values().for_each(|v| collect(v, out)),
+            _ => {}
+        }
+    }
+    let mut out = String::new();
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .filter(|(k, _)| !k.starts_with('_'))
+            .filter(|(k, _)| {
+                !matches!(k.as_str(), "session_id" | "tool_use_id" | "transcript_path")
+            })
+            .for_each(|(_, v)| collect(v, &mut out)),
+        other => collect(other, &mut out),
+    }
+    out
+}
+
+/// An FTS5 query matching events with any of the terms (more matches rank
+/// higher), each quoted so user input can't be read as query syntax.
+#[cfg(feature = "sqlite")]
+fn match_expression(query: &str) -> Option<String> {
+    let terms = tokenize(query);
+    (!terms.is_empty()).then(|| {
+        terms
+            .iter()
+            .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" OR ")
+    })
+}
+
+/// Create events_fts if the store doesn't have one yet, indexing the events
+/// already in it (stores written before the index existed).
+#[cfg(feature = "sqlite")]
+pub(crate) fn create_search_index(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT count(*) > 0 FROM sqlite_master WHERE name = 'events_fts'",
+        [],
+        |row| row.get(0),
+    )?;
+    if exists {
+        return Ok(());
+    }
+    conn.execute_batch("BEGIN; CREATE VIRTUAL TABLE events_fts USING fts5(body);")?;
+    let backfill = (|| {
+        let mut select = conn.prepare("SELECT id, raw FROM events")?;
+        let mut insert = conn.prepare("INSERT INTO events_fts (rowid, body) VALUES (?1, ?2)")?;
+        let rows = select.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (id, raw) = row?;
+            let event = serde_json::from_str(&raw).unwrap_or(Value::Null);
+            insert.execute((id, searchable_text(&event)))?;
+        }
+        Ok(())
+    })();
+    conn.execute_batch(if backfill.is_ok() {
+        "COMMIT"
+    } else {
+        "ROLLBACK"
+    })?;
+    backfill
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    /// BM25-ranked matches for a free-text query, best first.
+    pub(crate) fn search(&self, query: &str, limit: usize) -> rusqlite::Result<Vec<SearchHit>> {
+        let Some(expression) = match_expression(query) else {
+            return Ok(Vec::new());
+        };
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT -bm25(events_fts), snippet(events_fts, 0, '', '', '…', ?3), events.raw
+             FROM events_fts JOIN events ON events.id = events_fts.rowid
+             WHERE events_fts MATCH ?1
+             ORDER BY bm25(events_fts), events.id DESC
+             LIMIT ?2",
+        )?;
+        let rows = stmt.query_map((expression, limit as i64, SNIPPET_TOKENS as i64), |row| {
+            Ok(SearchHit {
+                score: row.get(0)?,
+                // One line: the indexed text separates values with newlines
+                snippet: row
+                    .get::<_, String>(1)?
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                event: serde_json::from_str(&row.get::<_, String>(2)?).unwrap_or(Value::Null),
+            })
+        })?;
+        rows.collect()
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+impl SqliteStore {
+    pub(crate) fn search(&self, _query: &str, _limit: usize) -> Result<Vec<SearchHit>, String> {
+        match *self {}
+    }
+}
+
+pub(crate) fn search_results_json(hits: &[SearchHit]) -> Value {
+    let results: Vec<Value> = hits
+        .iter()
+        .map(|h| {
+            serde_json::json!({
+                "score": (h.score * 1000.0).round() / 1000.0,
+                "snippet": h.snippet,
+                "event": h.event,
+            })
+        })
+        .collect();
+    serde_json::json!({"results": results})
+}
+
+/// `query`: search the index of an existing store (never creates one).
+#[cfg(feature = "sqlite")]
+pub(crate) fn run_query(from: &str, search: &str, limit: usize) -> std::io::Result<()> {
+    let path = std::path::Path::new(from);
+    if path.extension().is_some_and(|e| e == "jsonl") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "query searches a store's index, not a capture; index this one once with \
+                 `export --from {} --to events.db`, or run the server with --store",
+                from
+            ),
+        ));
+    }
+    if !path.is_file() {
+        return Err(std::io::ErrorKind::NotFound.into());
+    }
+    let to_io = |e: rusqlite::Error| std::io::Error::other(e.to_string());
+    let store = SqliteStore::open(from).map_err(to_io)?;
+    let mut out = std::io::stdout().lock();
+    for hit in store.search(search, limit).map_err(to_io)? {
+        let field = |k: &str| hit.event.get(k).map(value_text).unwrap_or_default();
+        writeln!(
+            out,
+            "{:>7.3}  {}  {}  {}",
+            hit.score,
+            field("_ts"),
+            field("_event"),
+            hit.snippet
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub(crate) fn run_query(_from: &str, _search: &str, _limit: usize) -> std::io::Result<()> {
+    Err(unsupported("sqlite"))
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_search_ranks_and_snippets() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        store.insert(&serde_json::json!({"_event": "PostToolUse", "tool_response": {"stderr": "cargo build failed: missing crate"}})).unwrap();
+        store
+            .insert(&serde_json::json!({"_event": "PreToolUse", "tool_input": {"command": "cargo build"}}))
+            .unwrap();
+        store
+            .insert(&serde_json::json!({"_event": "Notification", "message": "idle"}))
+            .unwrap();
+
+        let hits = store.search("cargo build failed", 10).unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].event["_event"], "PostToolUse");
+        assert!(hits[0].score > hits[1].score);
+        assert!(hits[0].snippet.contains("cargo build failed"));
+
+        // Query syntax in the input is just more terms
+        assert!(store.search("\"idle OR (", 10).unwrap().len() == 1);
+        assert!(store.search("", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search_index_backfills_older_stores() {
+        let dir = std::env::temp_dir().join(format!("rust-obs-fts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.db").to_string_lossy().to_string();
+        {
+            let store = SqliteStore::open(&path).unwrap();
+            store
+                .insert(&serde_json::json!({"_event": "UserPromptSubmit", "prompt": "fix the flaky test"}))
+                .unwrap();
+            // As written before the index existed
+            store.conn.execute_batch("DROP TABLE events_fts").unwrap();
+        }
+        let store = SqliteStore::open(&path).unwrap();
+        let hits = store.search("flaky", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].event["prompt"], "fix the flaky test");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
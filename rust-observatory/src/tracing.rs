@@ -0,0 +1,88 @@
+//! Stand-in for the `tracing` crate when the binary is built without the
+//! `tracing` feature. The same `tracing::warn!(path = %p, "message")` calls
+//! print the same one-line format on stderr, filtered by --log-level or a
+//! plain level in RUST_LOG; filter directives need the real thing.
+
+use super::*;
+use std::sync::atomic::AtomicU8;
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// --log-level, or RUST_LOG if it is a bare level name, or info.
+pub(crate) fn init(level: Option<LogLevel>) {
+    let level = level
+        .or_else(|| LogLevel::from_str(&std::env::var("RUST_LOG").ok()?, true).ok())
+        .unwrap_or(LogLevel::Info);
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub(crate) fn enabled(level: LogLevel) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+pub(crate) fn emit(level: LogLevel, message: String, fields: &str) {
+    let name = level
+        .to_possible_value()
+        .map_or(String::new(), |v| v.get_name().to_uppercase());
+    eprintln!(
+        "{} {:>5} {}{}",
+        Utc::now().format("%Y-%m-%dT%H:%M:%S%.6fZ"),
+        name,
+        message,
+        fields
+    );
+}
+
+/// `event!(Warn, field = %display, field = debug, %display, debug, "message {}", arg)`
+macro_rules! event {
+    (@$level:ident $out:ident; $name:ident = %$value:expr, $($rest:tt)+) => {{
+        $out.push_str(&format!(" {}={}", stringify!($name), $value));
+        $crate::tracing::event!(@$level $out; $($rest)+)
+    }};
+    (@$level:ident $out:ident; $name:ident = $value:expr, $($rest:tt)+) => {{
+        $out.push_str(&format!(" {}={:?}", stringify!($name), $value));
+        $crate::tracing::event!(@$level $out; $($rest)+)
+    }};
+    (@$level:ident $out:ident; %$name:ident, $($rest:tt)+) => {{
+        $out.push_str(&format!(" {}={}", stringify!($name), $name));
+        $crate::tracing::event!(@$level $out; $($rest)+)
+    }};
+    (@$level:ident $out:ident; $name:ident, $($rest:tt)+) => {{
+        $out.push_str(&format!(" {}={:?}", stringify!($name), $name));
+        $crate::tracing::event!(@$level $out; $($rest)+)
+    }};
+    (@$level:ident $out:ident; $($message:tt)+) => {
+        $crate::tracing::emit($crate::LogLevel::$level, format!($($message)+), &$out)
+    };
+    ($level:ident, $($body:tt)+) => {
+        if $crate::tracing::enabled($crate::LogLevel::$level) {
+            #[allow(unused_mut)]
+            let mut fields = String::new();
+            $crate::tracing::event!(@$level fields; $($body)+)
+        }
+    };
+}
+
+macro_rules! error {
+    ($($body:tt)+) => { $crate::tracing::event!(Error, $($body)+) };
+}
+
+// Named apart from the built-in #[warn] attribute, which a bare `warn`
+// re-export would be ambiguous with
+macro_rules! warn_event {
+    ($($body:tt)+) => { $crate::tracing::event!(Warn, $($body)+) };
+}
+
+macro_rules! info {
+    ($($body:tt)+) => { $crate::tracing::event!(Info, $($body)+) };
+}
+
+macro_rules! debug {
+    ($($body:tt)+) => { $crate::tracing::event!(Debug, $($body)+) };
+}
+
+macro_rules! trace {
+    ($($body:tt)+) => { $crate::tracing::event!(Trace, $($body)+) };
+}
+
+pub(crate) use {debug, error, event, info, trace, warn_event as warn};
@@ -88,155 +88,2136 @@ fn send_request(
     (status, body_out)
 }
 
+/// Without the `regex` feature the server only starts with redaction off.
+const NO_REDACT: &[&str] = if cfg!(feature = "regex") {
+    &[]
+} else {
+    &["--redact", "off"]
+};
+
+/// Stop a server cleanly (SIGTERM) and collect its output. Unlike kill(),
+/// this lets the sink threads write out what they still hold, so stdout has
+/// every event that was published.
+fn stop_server(child: Child) -> std::process::Output {
+    unsafe { libc::kill(child.id() as i32, libc::SIGTERM) };
+    child.wait_with_output().unwrap()
+}
+
 // === TCP INTEGRATION TESTS ===
 
 /// Start a TCP server on a unique port and return (child, port).
 fn start_tcp_server(port: u16) -> Child {
-    let child = Command::new(binary_path())
+    start_tcp_server_with(port, &[])
+}
+
+/// Start a TCP server with extra command-line flags.
+fn start_tcp_server_with(port: u16, extra_args: &[&str]) -> Child {
+    let mut child = Command::new(binary_path())
         .arg("tcp")
         .arg("--port")
         .arg(port.to_string())
+        .args(NO_REDACT)
+        .args(extra_args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
         .expect("Failed to start TCP server");
 
-    // Wait for server to start listening
-    for _ in 0..50 {
-        if TcpStream::connect(format!("127.0.0.1:{}", port)).is_ok() {
-            return child;
-        }
-        std::thread::sleep(Duration::from_millis(100));
+    // Wait for server to start listening
+    for _ in 0..50 {
+        if TcpStream::connect(format!("127.0.0.1:{}", port)).is_ok() {
+            return child;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    panic!("TCP server did not start within 5 seconds on port {}", port);
+}
+
+#[test]
+fn test_tcp_health_returns_ok() {
+    let port = unique_port();
+    let mut child = start_tcp_server(port);
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+
+    let (status, _) = send_request(
+        &mut stream,
+        &mut writer,
+        "POST",
+        "/hook?event=Stop",
+        Some(r#"{"session_id":"s1"}"#),
+    );
+    assert_eq!(status, 200);
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let (status, body) = send_request(&mut stream, &mut writer, "GET", "/health", None);
+    assert_eq!(status, 200);
+    let parsed: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(parsed["status"], "ok");
+    assert_eq!(parsed["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(parsed["transport"], "tcp");
+    assert!(parsed["uptime_secs"].is_u64());
+    assert_eq!(parsed["events"]["published"], 1);
+    assert_eq!(parsed["events"]["by_event"]["Stop"], 1);
+
+    child.kill().unwrap();
+    let _ = child.wait();
+}
+
+#[test]
+fn test_tcp_stats_counts_events_tools_and_sessions() {
+    let port = unique_port();
+    let mut child = start_tcp_server(port);
+    let request = |method: &str, path: &str, body: Option<&str>| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        send_request(&mut stream, &mut writer, method, path, body)
+    };
+
+    let (status, body) = request("GET", "/stats", None);
+    assert_eq!(status, 200);
+    let stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(stats["published"], 0);
+    assert!(stats["last_event_ts"].is_null());
+
+    let bash = r#"{"session_id":"s1","tool_name":"Bash","tool_input":{"command":"ls"}}"#;
+    let read = r#"{"session_id":"s2","tool_name":"Read","tool_input":{"file_path":"a"}}"#;
+    for (path, body) in [
+        ("/hook?event=PreToolUse", bash),
+        ("/hook?event=PostToolUse", bash),
+        ("/hook?event=PreToolUse", read),
+        ("/hook?event=Stop", r#"{"session_id":"s1"}"#),
+    ] {
+        assert_eq!(request("POST", path, Some(body)).0, 200);
+    }
+
+    let (status, body) = request("GET", "/stats", None);
+    assert_eq!(status, 200);
+    let stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(stats["published"], 4);
+    assert_eq!(stats["by_event"]["PreToolUse"], 2);
+    assert_eq!(stats["by_event"]["Stop"], 1);
+    assert_eq!(stats["by_tool"]["Bash"], 2);
+    assert_eq!(stats["by_tool"]["Read"], 1);
+    assert_eq!(stats["by_session"]["s1"], 3);
+    assert_eq!(stats["by_session"]["s2"], 1);
+
+    // A query string doesn't change the route
+    let (status, body) = request("GET", "/stats?probe=1", None);
+    assert_eq!(status, 200);
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&body).unwrap()["published"],
+        4
+    );
+    assert_eq!(request("GET", "/health?probe=1", None).0, 200);
+    let (status, body) = request("GET", "/metrics?probe=1", None);
+    assert_eq!(status, 200);
+    assert!(body.contains("# TYPE"), "{}", body);
+    assert!(stats["last_event_ts"].as_str().unwrap().ends_with("+00:00"));
+
+    child.kill().unwrap();
+    let _ = child.wait();
+}
+
+#[test]
+fn test_tcp_stats_and_metrics_report_request_latency() {
+    let port = unique_port();
+    let mut child = start_tcp_server_with(port, &["--handling-ms"]);
+    // One connection, so each request is timed before the next is read
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let body = r#"{"session_id":"s1","tool_name":"Bash"}"#;
+    let hook = format!(
+        "POST /hook?event=PreToolUse HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let requests =
+        hook.repeat(3) + "GET /stats HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    stream.write_all(requests.as_bytes()).unwrap();
+    let mut responses = String::new();
+    stream.read_to_string(&mut responses).unwrap();
+    assert_eq!(responses.matches("HTTP/1.1 200 OK\r\n").count(), 4);
+    let (_, body) = responses.rsplit_once("\r\n\r\n").unwrap();
+    let stats: serde_json::Value = serde_json::from_str(body).unwrap();
+    let post = &stats["latency_ms"]["POST"];
+    assert_eq!(post["requests"], 3);
+    let p50 = post["p50"].as_f64().unwrap();
+    assert!(p50 > 0.0 && p50 <= post["p99"].as_f64().unwrap());
+
+    let request = |path: &str| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        send_request(&mut stream, &mut writer, "GET", path, None).1
+    };
+    let metrics = request("/metrics");
+    assert!(metrics.contains("# TYPE observatory_request_duration_seconds summary"));
+    assert!(metrics.contains("observatory_request_duration_seconds_count{method=\"POST\"} 3\n"));
+
+    let events: serde_json::Value = serde_json::from_str(&request("/events")).unwrap();
+    assert_eq!(events.as_array().unwrap().len(), 3);
+    for event in events.as_array().unwrap() {
+        assert!(event["_handling_ms"].as_f64().unwrap() >= 0.0);
+    }
+
+    child.kill().unwrap();
+    let _ = child.wait();
+}
+
+#[test]
+fn test_tcp_head_health_has_headers_only() {
+    let port = unique_port();
+    let mut child = start_tcp_server(port);
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let (_, body) = send_request(&mut stream, &mut writer, "GET", "/health", None);
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        stream,
+        "HEAD /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    // The GET body's length, and nothing after the headers
+    let length = format!("\r\nContent-Length: {}\r\n", body.len());
+    assert!(response.contains(&length), "{}", response);
+    assert!(response.ends_with("\r\n\r\n"), "{}", response);
+
+    child.kill().unwrap();
+    let _ = child.wait();
+}
+
+#[test]
+fn test_tcp_hook_returns_200() {
+    let port = unique_port();
+    let mut child = start_tcp_server(port);
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+
+    let payload = r#"{"tool_name":"Bash","tool_input":{"command":"ls"}}"#;
+    let (status, body) = send_request(
+        &mut stream,
+        &mut writer,
+        "POST",
+        "/hook?event=PreToolUse",
+        Some(payload),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(body, "");
+
+    child.kill().unwrap();
+    let _ = child.wait();
+}
+
+#[test]
+fn test_tcp_outputs_enriched_jsonl() {
+    let port = unique_port();
+    let child = start_tcp_server(port);
+
+    // Send a hook event
+    {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let payload = r#"{"tool_name":"Edit"}"#;
+        let (status, _) = send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            "/hook?event=PostToolUse",
+            Some(payload),
+        );
+        assert_eq!(status, 200);
+    }
+
+    // Give server time to process and flush stdout
+    std::thread::sleep(Duration::from_millis(500));
+
+    // Kill and read stdout
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Should contain enriched JSONL
+    let lines: Vec<&str> = stdout
+        .trim()
+        .split('\n')
+        .filter(|l| !l.is_empty())
+        .collect();
+    assert!(
+        !lines.is_empty(),
+        "Expected JSONL output on stdout, got nothing"
+    );
+
+    let event: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(event["_event"], "PostToolUse");
+    assert_eq!(event["tool_name"], "Edit");
+    assert!(event["_ts"].is_string());
+    assert!(event["_client"].is_string()); // TCP includes _client
+}
+
+#[test]
+fn test_tcp_query_parameters_become_query_field() {
+    let port = unique_port();
+    let child = start_tcp_server(port);
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let (status, _) = send_request(
+        &mut stream,
+        &mut writer,
+        "POST",
+        "/hook?event=PreToolUse&profile=work&repo=foo&tag=a&tag=b%20c",
+        Some(r#"{"tool_name":"Bash"}"#),
+    );
+    assert_eq!(status, 200);
+
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let event: serde_json::Value = serde_json::from_str(stdout.lines().next().unwrap()).unwrap();
+    assert_eq!(event["_event"], "PreToolUse");
+    assert_eq!(
+        event["_query"],
+        serde_json::json!({"profile": "work", "repo": "foo", "tag": ["a", "b c"]})
+    );
+}
+
+#[test]
+fn test_tcp_content_type_415_and_accept_any() {
+    let post = |port: u16, content_type: &str, body: &str| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(
+            stream,
+            "POST /hook?event=Stop HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            content_type,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response.lines().next().unwrap_or("").to_string()
+    };
+    let events = |child: Child| {
+        let output = stop_server(child);
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let port = unique_port();
+    let child = start_tcp_server(port);
+    let json = "application/json; charset=utf-8";
+    assert_eq!(post(port, json, r#"{"a":1}"#), "HTTP/1.1 200 OK");
+    let form = "application/x-www-form-urlencoded";
+    assert_eq!(
+        post(port, form, "a=1"),
+        "HTTP/1.1 415 Unsupported Media Type"
+    );
+    let seen = events(child);
+    assert_eq!(seen.len(), 1);
+    assert_eq!(seen[0]["_content_type"], json);
+
+    // Accepted anyway: a body that isn't JSON is kept as _raw
+    let port = unique_port();
+    let child = start_tcp_server_with(port, &["--accept-any-content-type"]);
+    assert_eq!(post(port, form, "a=1"), "HTTP/1.1 200 OK");
+    let seen = events(child);
+    assert_eq!(seen[0]["_raw"], "a=1");
+    assert_eq!(seen[0]["_content_type"], form);
+}
+
+#[test]
+fn test_tcp_cors_preflight_and_origin() {
+    let port = unique_port();
+    let child = start_tcp_server_with(port, &["--cors", "http://play.local"]);
+    let send = |raw: &str| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(raw.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    // The browser's preflight
+    let response = send(
+        "OPTIONS /hook?event=Stop HTTP/1.1\r\nOrigin: http://play.local\r\nAccess-Control-Request-Method: POST\r\nAccess-Control-Request-Headers: content-type\r\nConnection: close\r\n\r\n",
+    );
+    assert!(
+        response.starts_with("HTTP/1.1 204 No Content\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.contains("\r\nAccess-Control-Allow-Origin: http://play.local\r\n"));
+    assert!(response.contains("\r\nAccess-Control-Allow-Methods: GET, POST, OPTIONS\r\n"));
+    assert!(response.contains("\r\nAccess-Control-Allow-Headers: content-type\r\n"));
+    assert!(!response.contains("Content-Length"), "{}", response);
+
+    // The hook itself, from the allowed origin and from another
+    let post = |origin: &str| {
+        send(&format!(
+            "POST /hook?event=Stop HTTP/1.1\r\nOrigin: {}\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}",
+            origin
+        ))
+    };
+    let response = post("http://play.local");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("\r\nAccess-Control-Allow-Origin: http://play.local\r\n"));
+    assert!(response.contains("\r\nVary: Origin\r\n"));
+    let response = post("http://elsewhere.example");
+    assert!(
+        !response.contains("Access-Control-Allow-Origin"),
+        "{}",
+        response
+    );
+
+    let output = stop_server(child);
+    // Both hooks were published (CORS is enforced by the browser)
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 2);
+}
+
+#[test]
+fn test_tcp_accept_selects_reply_format() {
+    let port = unique_port();
+    let mut child = start_tcp_server(port);
+    let get = |path: &str, accept: &str| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nAccept: {}\r\nConnection: close\r\n\r\n",
+            path, accept
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let payload = r#"{"tool_name":"Bash","tool_input":{"command":"ls"}}"#;
+    let (status, _) = send_request(
+        &mut stream,
+        &mut writer,
+        "POST",
+        "/hook?event=PreToolUse",
+        Some(payload),
+    );
+    assert_eq!(status, 200);
+
+    let response = get("/health", "text/plain");
+    assert!(
+        response.contains("\r\nContent-Type: text/plain; charset=utf-8\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.contains("\r\nVary: Accept\r\n"), "{}", response);
+    assert!(response.contains("\nstatus: ok\n"), "{}", response);
+    assert!(
+        response.contains("\nevents.by_event.PreToolUse: 1\n"),
+        "{}",
+        response
+    );
+
+    let response = get("/events", "text/plain");
+    assert!(response.ends_with(" PreToolUse Bash  ls\n"), "{}", response);
+
+    let response = get("/events", "text/html, application/json;q=0.9");
+    let body = response.split_once("\r\n\r\n").unwrap().1;
+    let events: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(events[0]["tool_name"], "Bash");
+
+    #[cfg(feature = "yaml")]
+    {
+        let response = get("/health", "application/yaml");
+        assert!(
+            response.contains("\r\nContent-Type: application/yaml\r\n"),
+            "{}",
+            response
+        );
+        assert!(response.contains("\nstatus: ok\n"), "{}", response);
+    }
+
+    child.kill().unwrap();
+    let _ = child.wait();
+}
+
+#[test]
+fn test_tcp_garbage_request_lines_get_400() {
+    let port = unique_port();
+    let mut child = start_tcp_server(port);
+    let long_method = format!("{} /hook HTTP/1.1\r\n\r\n", "A".repeat(100));
+    let long_target = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10_000));
+    let garbage: [&[u8]; 6] = [
+        b"POST\0/hook HTTP/1.1\r\n\r\n",
+        b"\xff\xfeST /hook HTTP/1.1\r\n\r\n",
+        b"POST /hook?event=Stop HTTP/1.1 trailing\r\n\r\n",
+        b"\0\0\0\0\r\n\r\n",
+        long_method.as_bytes(),
+        long_target.as_bytes(),
+    ];
+    for raw in garbage {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(raw).unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{:?}: {}",
+            String::from_utf8_lossy(&raw[..raw.len().min(40)]),
+            response
+        );
+        assert!(
+            response.contains("\r\nConnection: close\r\n"),
+            "{}",
+            response
+        );
+    }
+
+    // Still serving, and nothing was published
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let (status, body) = send_request(&mut stream, &mut writer, "GET", "/health", None);
+    assert_eq!(status, 200);
+    let health: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(health["events"]["published"], 0);
+
+    child.kill().unwrap();
+    let _ = child.wait();
+}
+
+#[test]
+fn test_tcp_log_level_controls_stderr() {
+    let run = |level: &str| {
+        let port = unique_port();
+        let child = start_tcp_server_with(port, &["--log-level", level]);
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let (status, _) = send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            "/hook?event=Stop",
+            Some("{not json"),
+        );
+        assert_eq!(status, 400);
+        let output = stop_server(child);
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    let debug = run("debug");
+    assert!(
+        debug.contains("DEBUG Connection accepted client=127.0.0.1:"),
+        "{}",
+        debug
+    );
+    assert!(
+        debug.contains(r#"INFO Request refused method="POST" path="/hook?event=Stop" status=400"#),
+        "{}",
+        debug
+    );
+    assert!(debug.contains(r#""code":"malformed_json""#), "{}", debug);
+
+    let errors_only = run("error");
+    assert!(!errors_only.contains("Request refused"), "{}", errors_only);
+    assert!(
+        !errors_only.contains("Connection accepted"),
+        "{}",
+        errors_only
+    );
+    // The startup banner is not a log line
+    assert!(errors_only.contains("listening on"), "{}", errors_only);
+}
+
+#[test]
+fn test_tcp_405_for_get_hook() {
+    let port = unique_port();
+    let mut child = start_tcp_server(port);
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        stream,
+        "GET /hook?event=PreToolUse HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.contains("\r\nAllow: POST\r\n"), "{}", response);
+
+    child.kill().unwrap();
+    let _ = child.wait();
+}
+
+#[test]
+fn test_tcp_status_codes_for_bad_requests() {
+    let port = unique_port();
+    let child = start_tcp_server(port);
+    let send = |raw: String| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(raw.as_bytes()).unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+    };
+    let post = |path: &str, body: &str| {
+        send(format!(
+            "POST {} HTTP/1.1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            body.len(),
+            body
+        ))
+    };
+    let status = |response: &str| response.lines().next().unwrap_or("").to_string();
+
+    let response = send("BOGUS\r\n\r\n".to_string());
+    assert_eq!(status(&response), "HTTP/1.1 400 Bad Request");
+    assert!(response.contains(r#"{"error":{"code":"malformed_request_line","#));
+    let response = post("/hook?event=Stop", "{not json");
+    assert_eq!(status(&response), "HTTP/1.1 400 Bad Request");
+    assert!(response.contains(r#"{"error":{"code":"malformed_json","#));
+    assert!(
+        response.contains(r#""message":"body is not JSON: "#),
+        "{}",
+        response
+    );
+    let response = post("/health", "{}");
+    assert_eq!(status(&response), "HTTP/1.1 405 Method Not Allowed");
+    assert!(
+        response.contains("\r\nAllow: GET, HEAD\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.ends_with(
+        r#"{"error":{"code":"method_not_allowed","message":"/health answers GET, HEAD, not POST"}}"#
+    ));
+    let response = send("GET /nowhere HTTP/1.1\r\nConnection: close\r\n\r\n".to_string());
+    assert_eq!(status(&response), "HTTP/1.1 404 Not Found");
+    assert!(response
+        .ends_with(r#"{"error":{"code":"not_found","message":"no route for GET /nowhere"}}"#));
+    let response = send(format!(
+        "POST /hook HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+        "x".repeat(70_000)
+    ));
+    assert_eq!(
+        status(&response),
+        "HTTP/1.1 431 Request Header Fields Too Large"
+    );
+    let response = send("POST /hook HTTP/1.1\r\nContent-Length: 2x\r\n\r\n{}".to_string());
+    assert_eq!(status(&response), "HTTP/1.1 400 Bad Request");
+    assert!(response.contains(r#"{"error":{"code":"bad_content_length","#));
+    // Conflicting lengths and a signed one can't frame the body either
+    for lengths in [
+        "Content-Length: 2\r\nContent-Length: 10",
+        "Content-Length: +2",
+    ] {
+        let response = send(format!(
+            "POST /hook?event=Stop HTTP/1.1\r\n{}\r\n\r\n{{}}",
+            lengths
+        ));
+        assert_eq!(status(&response), "HTTP/1.1 400 Bad Request", "{}", lengths);
+        assert!(response.contains(r#"{"error":{"code":"bad_content_length","#));
+    }
+    // chunked not last: no framing, so not read by Content-Length either
+    let response = send(
+        "POST /hook HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\nContent-Length: 2\r\n\r\n{}"
+            .to_string(),
+    );
+    assert_eq!(status(&response), "HTTP/1.1 400 Bad Request");
+    assert!(response.contains(r#"{"error":{"code":"malformed_chunked_body","#));
+    // A request cut short by the client closing is dropped unanswered
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+        .write_all(b"POST /hook?event=Stop HTTP/1.1\r\nContent-Length: 20\r\n\r\n{}")
+        .unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response);
+    assert_eq!(response, "");
+    // A hook must name its event
+    let response = post("/hook", "{}");
+    assert_eq!(status(&response), "HTTP/1.1 400 Bad Request");
+    assert!(response.ends_with(
+        r#"{"error":{"code":"missing_event","message":"/hook has no ?event=, e.g. /hook?event=PreToolUse"}}"#
+    ));
+    assert_eq!(
+        status(&post("/hook?event=", "{}")),
+        "HTTP/1.1 400 Bad Request"
+    );
+    // Hooks to other paths are still accepted
+    assert_eq!(status(&post("/?event=Stop", "{}")), "HTTP/1.1 200 OK");
+
+    // A clean stop, so stdout is flushed
+    let output = stop_server(child);
+    // Only the last request was published
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 1);
+}
+
+#[test]
+fn test_tcp_events_backfill() {
+    let port = unique_port();
+    let mut child = start_tcp_server(port);
+
+    for event_name in ["PreToolUse", "PostToolUse", "PreToolUse"] {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let path = format!("/hook?event={}", event_name);
+        let (status, _) = send_request(&mut stream, &mut writer, "POST", &path, Some("{}"));
+        assert_eq!(status, 200);
+    }
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let (status, body) = send_request(
+        &mut stream,
+        &mut writer,
+        "GET",
+        "/events?limit=5&event=PreToolUse",
+        None,
+    );
+    assert_eq!(status, 200);
+    let events: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|e| e["_event"] == "PreToolUse"));
+
+    // ULID _ids sort by arrival, so ?after= resumes from a bookmark
+    let first = events[0]["_id"].as_str().unwrap().to_string();
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let path = format!("/events?after={}", first);
+    let (status, body) = send_request(&mut stream, &mut writer, "GET", &path, None);
+    assert_eq!(status, 200);
+    let later: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+    assert_eq!(later.len(), 2);
+    assert_eq!(later[0]["_event"], "PostToolUse");
+
+    child.kill().unwrap();
+    let _ = child.wait();
+}
+
+#[test]
+fn test_federate_polls_tcp_source_and_serves_queries() {
+    let (upstream_port, query_port) = (unique_port(), unique_port());
+    let mut upstream = start_tcp_server(upstream_port);
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", upstream_port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let (status, _) = send_request(
+        &mut stream,
+        &mut writer,
+        "POST",
+        "/hook?event=PreToolUse",
+        Some("{}"),
+    );
+    assert_eq!(status, 200);
+
+    let mut federate = Command::new(binary_path())
+        .arg("federate")
+        .arg("--source")
+        .arg(format!("ci=http://127.0.0.1:{}", upstream_port))
+        .arg("--listen")
+        .arg(format!("127.0.0.1:{}", query_port))
+        .args(NO_REDACT)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start federate");
+
+    // The first poll backfills the upstream's history
+    let mut merged: Vec<serde_json::Value> = Vec::new();
+    for _ in 0..50 {
+        std::thread::sleep(Duration::from_millis(100));
+        let Ok(mut stream) = TcpStream::connect(format!("127.0.0.1:{}", query_port)) else {
+            continue;
+        };
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let (_, body) = send_request(&mut stream, &mut writer, "GET", "/events", None);
+        merged = serde_json::from_str(&body).unwrap_or_default();
+        if !merged.is_empty() {
+            break;
+        }
+    }
+    assert_eq!(merged.len(), 1, "merged: {:?}", merged);
+    assert_eq!(merged[0]["_event"], "PreToolUse");
+    assert_eq!(merged[0]["_source"], "ci");
+
+    // Queries only: hooks go to the upstreams
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", query_port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let (status, _) = send_request(&mut stream, &mut writer, "POST", "/hook", Some("{}"));
+    assert_eq!(status, 405);
+
+    federate.kill().unwrap();
+    let _ = federate.wait();
+    upstream.kill().unwrap();
+    let _ = upstream.wait();
+}
+
+#[test]
+fn test_tcp_seq_continues_after_restart() {
+    let seq_file = format!("/tmp/rust-obs-test-{}-seq", std::process::id());
+    let _ = std::fs::remove_file(&seq_file);
+    let mut seqs = Vec::new();
+
+    for events in [2, 1] {
+        let port = unique_port();
+        let child = start_tcp_server_with(port, &["--seq-file", &seq_file]);
+        for _ in 0..events {
+            let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let (status, _) = send_request(
+                &mut stream,
+                &mut writer,
+                "POST",
+                "/hook?event=Stop",
+                // A client can't set its own number
+                Some(r#"{"_seq": 99}"#),
+            );
+            assert_eq!(status, 200);
+        }
+        std::thread::sleep(Duration::from_millis(300));
+        // A clean stop, which saves the last number handed out
+        let output = stop_server(child);
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let event: serde_json::Value = serde_json::from_str(line).unwrap();
+            seqs.push(event["_seq"].as_u64().unwrap());
+        }
+    }
+    let _ = std::fs::remove_file(&seq_file);
+
+    // The second run (a fresh process) picks up where the first left off
+    assert_eq!(seqs, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_tcp_rate_limit_returns_429() {
+    let port = unique_port();
+    let child = start_tcp_server_with(port, &["--rate-limit", "2/m"]);
+
+    let mut statuses = Vec::new();
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let (status, _) = send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            "/hook?event=PreToolUse",
+            Some("{}"),
+        );
+        statuses.push(status);
+    }
+    assert_eq!(statuses, vec![200, 200, 429]);
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let (status, body) = send_request(&mut stream, &mut writer, "GET", "/metrics", None);
+    assert_eq!(status, 200);
+    assert!(body.contains(r#"observatory_rate_limited_total{client="127.0.0.1"} 1"#));
+
+    let output = stop_server(child);
+    // The rejected event was never recorded
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 2);
+}
+
+#[test]
+fn test_tcp_format_for_overrides_per_event_type() {
+    let port = unique_port();
+    let child = start_tcp_server_with(
+        port,
+        &["--pretty-json", "--format-for", "PostToolUse=summary"],
+    );
+
+    for (event, body) in [
+        (
+            "PostToolUse",
+            r#"{"tool_name":"Bash","tool_input":{"command":"npm test"}}"#,
+        ),
+        ("Notification", r#"{"message":"Waiting for input"}"#),
+    ] {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let path = format!("/hook?event={}", event);
+        let (status, _) = send_request(&mut stream, &mut writer, "POST", &path, Some(body));
+        assert_eq!(status, 200);
+    }
+
+    std::thread::sleep(Duration::from_millis(200));
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    let summary = lines.next().unwrap();
+    assert!(
+        summary.ends_with(" PostToolUse Bash  npm test"),
+        "{}",
+        summary
+    );
+    // The rest is the Notification in the global format
+    let rest: Vec<&str> = lines.collect();
+    assert_eq!(rest.first(), Some(&"{"));
+    let pretty: serde_json::Value = serde_json::from_str(&rest.join("\n")).unwrap();
+    assert_eq!(pretty["message"], "Waiting for input");
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn test_tcp_min_level_shows_only_consequential_events() {
+    let port = unique_port();
+    let child = start_tcp_server_with(port, &["--min-level", "high"]);
+
+    for body in [
+        r#"{"tool_name":"Read","tool_input":{"file_path":"src/main.rs"}}"#,
+        r#"{"tool_name":"Bash","tool_input":{"command":"cargo test"}}"#,
+        r#"{"tool_name":"Bash","tool_input":{"command":"rm -rf target"}}"#,
+    ] {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let (status, _) = send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            "/hook?event=PreToolUse",
+            Some(body),
+        );
+        assert_eq!(status, 200);
+    }
+
+    std::thread::sleep(Duration::from_millis(200));
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let events: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(events.len(), 1, "{}", stdout);
+    assert_eq!(events[0]["_level"], "high");
+    assert_eq!(events[0]["tool_input"]["command"], "rm -rf target");
+}
+
+#[test]
+fn test_tcp_deny_rule_returns_permission_decision() {
+    let port = unique_port();
+    let child = start_tcp_server_with(port, &["--deny", "command~rm -rf"]);
+
+    let mut responses = Vec::new();
+    for command in ["rm -rf /tmp/x", "ls"] {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let body = serde_json::json!({"tool_name": "Bash", "tool_input": {"command": command}});
+        responses.push(send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            "/hook?event=PreToolUse",
+            Some(&body.to_string()),
+        ));
+    }
+
+    let (status, body) = &responses[0];
+    assert_eq!(*status, 200);
+    let decision: serde_json::Value = serde_json::from_str(body).unwrap();
+    let output = &decision["hookSpecificOutput"];
+    assert_eq!(output["hookEventName"], "PreToolUse");
+    assert_eq!(output["permissionDecision"], "deny");
+    assert!(output["permissionDecisionReason"]
+        .as_str()
+        .unwrap()
+        .contains("command~rm -rf"));
+    // No rule matched: the usual empty no-op answer
+    assert_eq!(responses[1], (200, String::new()));
+
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let first: serde_json::Value = serde_json::from_str(stdout.lines().next().unwrap()).unwrap();
+    assert_eq!(first["_decision"]["permission"], "deny");
+}
+
+#[test]
+#[cfg(all(feature = "regex", feature = "digests"))]
+fn test_tcp_audit_log_masks_secrets() {
+    let port = unique_port();
+    let audit = std::env::temp_dir().join(format!("observatory-audit-{}.jsonl", port));
+    let _ = std::fs::remove_file(&audit);
+    let mut child = start_tcp_server_with(
+        port,
+        &[
+            "--deny",
+            "command~curl",
+            "--audit-log",
+            audit.to_str().unwrap(),
+        ],
+    );
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let command = "curl -H 'Authorization: Bearer supersecrettoken123' https://example.com";
+    let body = serde_json::json!({"tool_name": "Bash", "tool_input": {"command": command}});
+    let (status, _) = send_request(
+        &mut stream,
+        &mut writer,
+        "POST",
+        "/hook?event=PreToolUse",
+        Some(&body.to_string()),
+    );
+    assert_eq!(status, 200);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let text = std::fs::read_to_string(&audit).unwrap();
+    let _ = std::fs::remove_file(&audit);
+    assert!(!text.contains("supersecrettoken123"), "{}", text);
+    let record: serde_json::Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+    assert_eq!(record["decided_by"], "rule");
+    assert!(record["tool_input"]["command"]
+        .as_str()
+        .unwrap()
+        .contains("[REDACTED:authorization]"));
+}
+
+#[test]
+#[cfg(all(feature = "yaml", feature = "regex"))]
+fn test_tcp_rule_message_is_rendered_masked() {
+    let port = unique_port();
+    let rules = std::env::temp_dir().join(format!("observatory-rules-{}.yaml", port));
+    std::fs::write(
+        &rules,
+        "- id: no-curl\n  command: curl\n  action: deny\n  message: 'Blocked {{command}}'\n",
+    )
+    .unwrap();
+    let child = start_tcp_server_with(
+        port,
+        &["--redact", "default", "--rules", rules.to_str().unwrap()],
+    );
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let command = "curl -H 'Authorization: Bearer supersecrettoken123' https://example.com";
+    let body = serde_json::json!({"tool_name": "Bash", "tool_input": {"command": command}});
+    let (status, body) = send_request(
+        &mut stream,
+        &mut writer,
+        "POST",
+        "/hook?event=PreToolUse",
+        Some(&body.to_string()),
+    );
+    let output = stop_server(child);
+    let _ = std::fs::remove_file(&rules);
+
+    // The rule matched the full command; its message shows the masked one
+    assert_eq!(status, 200);
+    let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        response["hookSpecificOutput"]["permissionDecisionReason"],
+        "Blocked curl -H 'Authorization: [REDACTED:authorization]' https://example.com"
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("supersecrettoken123"), "{}", stdout);
+}
+
+#[test]
+fn test_tcp_delay_response_and_timeout_simulate() {
+    let port = unique_port();
+    let child = start_tcp_server_with(
+        port,
+        &[
+            "--delay-response",
+            "PreToolUse=300ms",
+            "--timeout-simulate",
+            "Stop",
+        ],
+    );
+    let post = |event: &str, read_timeout: Duration| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream.set_read_timeout(Some(read_timeout)).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let started = std::time::Instant::now();
+        let response = send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            &format!("/hook?event={}", event),
+            Some("{}"),
+        );
+        (response, started.elapsed())
+    };
+
+    // Delayed, but answered
+    let (response, elapsed) = post("PreToolUse", Duration::from_secs(5));
+    assert_eq!(response, (200, String::new()));
+    assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+
+    // Never answered: the client gives up (its read times out)
+    let (response, elapsed) = post("Stop", Duration::from_millis(500));
+    assert_eq!(response.0, 0);
+    assert!(elapsed >= Duration::from_millis(500), "{:?}", elapsed);
+
+    // Once that client hangs up, the server moves on; other events are fast
+    let (response, elapsed) = post("PostToolUse", Duration::from_secs(5));
+    assert_eq!(response, (200, String::new()));
+    assert!(elapsed < Duration::from_millis(300), "{:?}", elapsed);
+
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // All three were shown, including the one never answered
+    assert_eq!(stdout.lines().count(), 3);
+}
+
+#[test]
+fn test_tcp_stalled_clients_do_not_block_others() {
+    let port = unique_port();
+    let args: &[&str] = if cfg!(feature = "async") {
+        &["--workers", "3", "--timeout-simulate", "Stop"]
+    } else {
+        &["--timeout-simulate", "Stop"] // a thread per connection
+    };
+    let child = start_tcp_server_with(port, args);
+
+    // One client sends half a request and stalls
+    let mut stalled = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stalled
+        .write_all(b"POST /hook?event=PreToolUse HTTP/1.1\r\nContent-Length: 2\r\n")
+        .unwrap();
+    // Another waits for an answer that never comes
+    let mut held = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    held.write_all(b"POST /hook?event=Stop HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}")
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    // Meanwhile, other hooks are answered promptly
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let started = std::time::Instant::now();
+        let response = send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            "/hook?event=PostToolUse",
+            Some("{}"),
+        );
+        assert_eq!(response, (200, String::new()));
+        assert!(
+            started.elapsed() < Duration::from_secs(1),
+            "{:?}",
+            started.elapsed()
+        );
+    }
+
+    // Closing the stalled client hands the server its partial request,
+    // which is dropped rather than published
+    drop((stalled, held));
+    std::thread::sleep(Duration::from_millis(200));
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // The held Stop and the three PostToolUse; the stalled one never arrived
+    assert_eq!(stdout.lines().count(), 4);
+}
+
+#[test]
+fn test_tcp_slow_responder_does_not_block_others() {
+    let port = unique_port();
+    // Slow to answer PreToolUse, no opinion on anything else
+    let responder = r#"if [ "$OBSERVATORY_EVENT" = PreToolUse ]; then sleep 2; echo '{"decision":"block","reason":"slow no"}'; fi"#;
+    let child = start_tcp_server_with(port, &["--responder", responder]);
+    let request = move |method: &str, path: &str, body: Option<&str>| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let started = std::time::Instant::now();
+        let response = send_request(&mut stream, &mut writer, method, path, body);
+        (response, started.elapsed())
+    };
+
+    let waiting = std::thread::spawn(move || request("POST", "/hook?event=PreToolUse", Some("{}")));
+    std::thread::sleep(Duration::from_millis(300));
+
+    // While the responder thinks, other hooks and the API are answered
+    let ((status, body), elapsed) = request("POST", "/hook?event=PostToolUse", Some("{}"));
+    assert_eq!((status, body.as_str()), (200, ""));
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    let ((status, _), elapsed) = request("GET", "/health", None);
+    assert_eq!(status, 200);
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+
+    // The first hook gets the responder's answer once it comes
+    let ((status, body), elapsed) = waiting.join().unwrap();
+    assert_eq!(status, 200);
+    assert!(body.contains("slow no"), "{}", body);
+    assert!(elapsed >= Duration::from_secs(2), "{:?}", elapsed);
+
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let events: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    // Published as answered: the PostToolUse first
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["_event"], "PostToolUse");
+    assert_eq!(events[1]["_event"], "PreToolUse");
+    assert_eq!(events[1]["_decision"]["rule"], "responder");
+}
+
+#[test]
+fn test_tcp_read_timeout_returns_408() {
+    let port = unique_port();
+    let child = start_tcp_server_with(
+        port,
+        &["--header-timeout", "300ms", "--read-timeout", "300ms"],
+    );
+    let read_response = |sent: &[u8]| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(sent).unwrap();
+        let started = std::time::Instant::now();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        (response, started.elapsed())
+    };
+
+    // Connects and sends nothing
+    let (response, elapsed) = read_response(b"");
+    assert!(
+        response.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.contains(r#"{"error":{"code":"request_timeout","#));
+    assert!(elapsed >= Duration::from_millis(250), "{:?}", elapsed);
+
+    // Stops halfway through the body
+    let (response, _) =
+        read_response(b"POST /hook?event=Stop HTTP/1.1\r\nContent-Length: 20\r\n\r\n{\"a\":");
+    assert!(response.starts_with("HTTP/1.1 408 "), "{}", response);
+
+    // A complete request is still answered
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let response = send_request(
+        &mut stream,
+        &mut writer,
+        "POST",
+        "/hook?event=Stop",
+        Some("{}"),
+    );
+    assert_eq!(response, (200, String::new()));
+
+    let output = stop_server(child);
+    // Only the complete request was published
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 1);
+}
+
+#[test]
+fn test_tcp_header_and_body_deadlines_are_separate() {
+    let port = unique_port();
+    let child = start_tcp_server_with(port, &["--header-timeout", "400ms", "--read-timeout", "3s"]);
+    let connect = || {
+        let stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    };
+
+    // Headers trickled a byte at a time: cut off at --header-timeout even
+    // though bytes keep arriving
+    let mut trickle = connect();
+    let mut writer = trickle.try_clone().unwrap();
+    let started = std::time::Instant::now();
+    std::thread::spawn(move || {
+        for byte in b"POST /hook?event=Stop HTTP/1.1\r\nX-Slow: yes\r\n\r\n" {
+            if writer.write_all(&[*byte]).is_err() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    });
+    let mut response = String::new();
+    let _ = trickle.read_to_string(&mut response);
+    assert!(response.starts_with("HTTP/1.1 408 "), "{}", response);
+    assert!(
+        started.elapsed() < Duration::from_secs(2),
+        "{:?}",
+        started.elapsed()
+    );
+
+    // Headers sent at once, body a second later: within --read-timeout
+    let mut slow_body = connect();
+    slow_body
+        .write_all(
+            b"POST /hook?event=Stop HTTP/1.1\r\nContent-Length: 8\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+    slow_body.write_all(br#"{"n":42}"#).unwrap();
+    let mut response = String::new();
+    slow_body.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains(r#""n":42"#), "{}", stdout);
+}
+
+#[test]
+fn test_tcp_large_headers_split_across_writes() {
+    let port = unique_port();
+    let child = start_tcp_server(port);
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.set_nodelay(true).unwrap();
+    // 30KB of headers in pieces, the blank line split between two writes
+    let request = format!(
+        "POST /hook?event=Stop HTTP/1.1\r\nX-Big: {}\r\nX-Folded: one\r\n two\r\nContent-Length: 8\r\nConnection: close\r\n\r\n{{\"n\":42}}",
+        "b".repeat(30_000)
+    );
+    let split = request.find("\r\n\r\n").unwrap() + 3;
+    for piece in [
+        &request[..10_000],
+        &request[10_000..split],
+        &request[split..],
+    ] {
+        stream.write_all(piece.as_bytes()).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains(r#""n":42"#), "{}", stdout);
+}
+
+#[test]
+fn test_tcp_pipelined_requests_on_one_connection() {
+    let port = unique_port();
+    let child = start_tcp_server_with(port, &[]);
+
+    // A replay client writes every event before reading any answer
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let sender = std::thread::spawn(move || {
+        let mut requests = String::new();
+        for n in 0..300 {
+            let body = format!(r#"{{"n":{}}}"#, n);
+            let connection = if n == 299 { "close" } else { "keep-alive" };
+            requests += &format!(
+                "POST /hook?event=PostToolUse HTTP/1.1\r\nHost: localhost\r\nConnection: {}\r\nContent-Length: {}\r\n\r\n{}",
+                connection,
+                body.len(),
+                body
+            );
+        }
+        writer.write_all(requests.as_bytes()).unwrap();
+    });
+    let mut responses = String::new();
+    stream.read_to_string(&mut responses).unwrap();
+    sender.join().unwrap();
+    assert_eq!(responses.matches("HTTP/1.1 200 OK\r\n").count(), 300);
+
+    // A clean stop, so stdout is flushed
+    let output = stop_server(child);
+    // Every event was published, in the order it was sent
+    let seen: Vec<u64> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<serde_json::Value>(line).unwrap()["n"]
+                .as_u64()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(seen, (0..300).collect::<Vec<u64>>());
+}
+
+#[test]
+fn test_tcp_http_10_connection_defaults() {
+    let port = unique_port();
+    let child = start_tcp_server(port);
+    let connect = || {
+        let stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    };
+    let request = |version: &str, connection: &str| {
+        format!(
+            "POST /hook?event=Stop HTTP/{}\r\n{}Content-Length: 2\r\n\r\n{{}}",
+            version, connection
+        )
+    };
+
+    // HTTP/1.0 closes by default, and says so
+    let mut stream = connect();
+    stream.write_all(request("1.0", "").as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(
+        response.contains("\r\nConnection: close\r\n"),
+        "{}",
+        response
+    );
+
+    // ... unless it asks to keep the connection: two answers on one
+    let mut stream = connect();
+    let keep = request("1.0", "Connection: keep-alive\r\n");
+    stream.write_all(keep.as_bytes()).unwrap();
+    stream.write_all(request("1.0", "").as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert_eq!(
+        response.matches("HTTP/1.1 200 OK\r\n").count(),
+        2,
+        "{}",
+        response
+    );
+    assert!(
+        response.contains("\r\nConnection: keep-alive\r\n"),
+        "{}",
+        response
+    );
+
+    // HTTP/1.1 stays open by default, and gets no Connection header
+    let mut stream = connect();
+    stream.write_all(request("1.1", "").as_bytes()).unwrap();
+    let mut head = [0u8; 17];
+    stream.read_exact(&mut head).unwrap();
+    assert_eq!(&head, b"HTTP/1.1 200 OK\r\n");
+    stream
+        .write_all(request("1.1", "Connection: close\r\n").as_bytes())
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert_eq!(
+        response.matches("Connection: close").count(),
+        1,
+        "{}",
+        response
+    );
+
+    let output = stop_server(child);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 5);
+}
+
+#[test]
+fn test_tcp_flush_interval_batches_stdout() {
+    let port = unique_port();
+    let mut child = start_tcp_server_with(port, &["--flush", "700ms"]);
+    let stdout = child.stdout.take().unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::BufRead::lines(std::io::BufReader::new(stdout)) {
+            let _ = tx.send((std::time::Instant::now(), line.unwrap()));
+        }
+    });
+
+    let sent = std::time::Instant::now();
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let (status, _) = send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            "/hook?event=Stop",
+            Some("{}"),
+        );
+        assert_eq!(status, 200);
+    }
+
+    // Answered at once, but shown together when the batch is flushed,
+    // without another event to trigger it
+    let arrivals: Vec<_> = (0..3)
+        .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap().0)
+        .collect();
+    for arrived in arrivals {
+        assert!(
+            arrived.duration_since(sent) >= Duration::from_millis(500),
+            "{:?}",
+            arrived.duration_since(sent)
+        );
+    }
+
+    child.kill().unwrap();
+    let _ = child.wait();
+}
+
+#[test]
+fn test_tcp_max_connections_refuses_with_503() {
+    let port = unique_port();
+    let child = start_tcp_server_with(port, &["--max-connections", "2"]);
+    let send = |method: &str, path: &str| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let body = (method == "POST").then_some("{}");
+        send_request(&mut stream, &mut writer, method, path, body)
+    };
+    // Until the server has seen the slots change, probing with a GET
+    // (published nowhere) rather than sleeping for a guessed while
+    let wait_for_health = |wanted: u16| {
+        for _ in 0..50 {
+            if send("GET", "/health").0 == wanted {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        panic!("/health never answered {}", wanted);
+    };
+
+    // Two idle keep-alive connections take both slots. Each counts once it
+    // was answered: the startup probe's connection may hold a slot for a
+    // moment longer, and a connection refused meanwhile is retried
+    let mut idle = Vec::new();
+    for _ in 0..50 {
+        if idle.len() == 2 {
+            break;
+        }
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut answer = [0u8; 12];
+        stream.read_exact(&mut answer).unwrap();
+        if &answer == b"HTTP/1.1 200" {
+            idle.push(stream);
+        }
+    }
+    assert_eq!(idle.len(), 2);
+    wait_for_health(503);
+    let (status, body) = send("POST", "/hook?event=Stop");
+    assert_eq!(status, 503);
+    let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(error["error"]["code"], "too_many_connections");
+
+    // A slot frees up when a connection closes
+    drop(idle);
+    wait_for_health(200);
+    assert_eq!(send("POST", "/hook?event=Stop"), (200, String::new()));
+
+    // A clean stop, so stdout is flushed
+    let output = stop_server(child);
+    // Only the admitted request was published
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 1);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("WARN Connection refused: --max-connections reached refused=1"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_tcp_max_body_returns_413() {
+    let port = unique_port();
+    let child = start_tcp_server_with(port, &["--max-body", "1K"]);
+    let post = |body: &str| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            "/hook?event=Stop",
+            Some(body),
+        )
+    };
+
+    let big = format!(r#"{{"blob":"{}"}}"#, "x".repeat(100_000));
+    let (status, body) = post(&big);
+    assert_eq!(status, 413);
+    let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(error["error"]["code"], "body_too_large");
+    assert!(error["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("--max-body"));
+    assert_eq!(post("{}"), (200, String::new()));
+
+    // Counted on /metrics
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let (_, metrics) = send_request(&mut stream, &mut writer, "GET", "/metrics", None);
+    assert!(
+        metrics.contains("observatory_requests_refused_total{status=\"413\"} 1\n"),
+        "{}",
+        metrics
+    );
+
+    let output = stop_server(child);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 1);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("WARN Request refused: body over --max-body refused=1"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_tcp_debug_self_reports_process_and_queues() {
+    let port = unique_port();
+    let log = std::env::temp_dir().join(format!("observatory-debug-self-{}.jsonl", port));
+    let mut child = start_tcp_server_with(port, &["--log-file", log.to_str().unwrap()]);
+    let request = |method: &str, path: &str, body: Option<&str>| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        send_request(&mut stream, &mut writer, method, path, body)
+    };
+
+    for _ in 0..3 {
+        assert_eq!(request("POST", "/hook?event=Stop", Some("{}")).0, 200);
     }
-    panic!("TCP server did not start within 5 seconds on port {}", port);
+    let (status, body) = request("GET", "/debug/self?probe=1", None);
+    assert_eq!(status, 200);
+    let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(report["pid"], child.id());
+    assert_eq!(report["events"]["published"], 3);
+    assert_eq!(report["events"]["by_event"]["Stop"], 3);
+    assert_eq!(report["history"]["events"], 3);
+    let sink = report["queues"]["sinks"][0]["name"].as_str().unwrap();
+    assert!(sink.starts_with("log file "), "{}", sink);
+    assert!(report["process"]["cpu_user_secs"].is_number());
+    assert!(report["process"]["rss_bytes"].as_u64().unwrap() > 0);
+    assert!(report["process"]["open_fds"].as_u64().unwrap() > 0);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let _ = std::fs::remove_file(&log);
 }
 
 #[test]
-fn test_tcp_health_returns_ok() {
+fn test_tcp_health_reports_configuration() {
     let port = unique_port();
-    let mut child = start_tcp_server(port);
+    let log = std::env::temp_dir().join(format!("observatory-health-{}.jsonl", port));
+    let mut child = start_tcp_server_with(
+        port,
+        &["--format", "summary", "--log-file", log.to_str().unwrap()],
+    );
 
     let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
     stream
         .set_read_timeout(Some(Duration::from_secs(5)))
         .unwrap();
     let mut writer = stream.try_clone().unwrap();
-
     let (status, body) = send_request(&mut stream, &mut writer, "GET", "/health", None);
     assert_eq!(status, 200);
-    let parsed: serde_json::Value = serde_json::from_str(&body).unwrap();
-    assert_eq!(parsed["status"], "ok");
+    let health: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert!(health["started_at"].as_str().unwrap().ends_with("+00:00"));
+    assert_eq!(health["output"]["format"], "summary");
+    assert!(health["output"]["readers"].is_null());
+    let features = health["features"].as_array().unwrap();
+    assert_eq!(features.contains(&"yaml".into()), cfg!(feature = "yaml"));
+    let sink = &health["sinks"][0];
+    assert!(
+        sink["name"].as_str().unwrap().starts_with("log file "),
+        "{}",
+        sink
+    );
+    assert_eq!(sink["running"], true);
+    assert_eq!(sink["errors"], 0);
 
     child.kill().unwrap();
-    let _ = child.wait();
+    child.wait().unwrap();
+    let _ = std::fs::remove_file(&log);
 }
 
 #[test]
-fn test_tcp_hook_returns_200() {
+fn test_tcp_chunked_body_is_decoded() {
     let port = unique_port();
-    let mut child = start_tcp_server(port);
-
-    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
-    stream
-        .set_read_timeout(Some(Duration::from_secs(5)))
+    let child = start_tcp_server_with(port, &["--max-body", "1K"]);
+    let send = |body: &str| {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(
+            stream,
+            "POST /hook?event=Stop HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{}",
+            body
+        )
         .unwrap();
-    let mut writer = stream.try_clone().unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
 
-    let payload = r#"{"tool_name":"Bash","tool_input":{"command":"ls"}}"#;
-    let (status, body) = send_request(
-        &mut stream,
-        &mut writer,
-        "POST",
-        "/hook?event=PreToolUse",
-        Some(payload),
+    let response = send("6\r\n{\"n\":4\r\n2\r\n2}\r\n0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    let response = send("zz\r\n{}\r\n0\r\n\r\n");
+    assert!(
+        response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.contains(r#"{"error":{"code":"malformed_chunked_body","#));
+    // An endless trailer is cut off like oversized headers, and chunk
+    // extensions count toward --max-body
+    let response = send(&format!("0\r\nX-T: {}", "t".repeat(70_000)));
+    assert!(
+        response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+        "{}",
+        response
+    );
+    let response = send(&format!("1;{}\r\na\r\n0\r\n\r\n", "e".repeat(2000)));
+    assert!(
+        response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"),
+        "{}",
+        response
     );
-    assert_eq!(status, 200);
-    assert_eq!(body, "");
 
-    child.kill().unwrap();
-    let _ = child.wait();
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 1);
+    let event: serde_json::Value = serde_json::from_str(stdout.lines().next().unwrap()).unwrap();
+    assert_eq!(event["n"], 42);
 }
 
 #[test]
-fn test_tcp_outputs_enriched_jsonl() {
+fn test_tcp_expect_100_continue() {
     let port = unique_port();
-    let mut child = start_tcp_server(port);
-
-    // Send a hook event
-    {
-        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    let child = start_tcp_server_with(port, &["--max-body", "1K"]);
+    let connect = || {
+        let stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
         stream
             .set_read_timeout(Some(Duration::from_secs(5)))
             .unwrap();
-        let mut writer = stream.try_clone().unwrap();
-        let payload = r#"{"tool_name":"Edit"}"#;
-        let (status, _) = send_request(
-            &mut stream,
-            &mut writer,
-            "POST",
-            "/hook?event=PostToolUse",
-            Some(payload),
-        );
-        assert_eq!(status, 200);
-    }
+        stream
+    };
+    let head = |len: usize| {
+        format!(
+            "POST /hook?event=Stop HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            len
+        )
+    };
 
-    // Give server time to process and flush stdout
-    std::thread::sleep(Duration::from_millis(500));
+    // Told to go on, then answered once the body is in
+    let mut stream = connect();
+    stream.write_all(head(8).as_bytes()).unwrap();
+    let mut interim = [0u8; 25];
+    stream.read_exact(&mut interim).unwrap();
+    assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+    stream.write_all(br#"{"n":42}"#).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
 
-    // Kill and read stdout
-    child.kill().unwrap();
-    let output = child.wait_with_output().unwrap();
+    // Over --max-body: refused before the body is sent
+    let mut stream = connect();
+    stream.write_all(head(1 << 20).as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+
+    let output = stop_server(child);
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains(r#""n":42"#), "{}", stdout);
+}
 
-    // Should contain enriched JSONL
-    let lines: Vec<&str> = stdout.trim().split('\n').filter(|l| !l.is_empty()).collect();
-    assert!(
-        !lines.is_empty(),
-        "Expected JSONL output on stdout, got nothing"
+#[test]
+#[cfg(feature = "forwarders")]
+fn test_tcp_slack_approval_posts_masked_command() {
+    // A stand-in Slack Web API: records each call, every one succeeds
+    let api = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let api_url = format!("http://{}", api.local_addr().unwrap());
+    let (calls, posted) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in api.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = String::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.push_str(&String::from_utf8_lossy(&buf[..n]));
+                let complete = request.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                    head.lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|v| v.trim().to_string())
+                        })
+                        .and_then(|len| len.parse::<usize>().ok())
+                        .is_some_and(|len| body.len() >= len)
+                });
+                if n == 0 || complete {
+                    break;
+                }
+            }
+            let body = r#"{"ok":true,"channel":"C1","ts":"1.5"}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+            let _ = calls.send(request);
+        }
+    });
+
+    let port = unique_port();
+    let slack_listen = format!("127.0.0.1:{}", unique_port());
+    let mut child = Command::new(binary_path())
+        .args(["tcp", "--port", &port.to_string()])
+        .args(["--ask", "command~curl", "--slack-approval", "#approvals"])
+        .args(["--slack-listen", &slack_listen, "--slack-timeout", "1s"])
+        .env("SLACK_BOT_TOKEN", "xoxb-test")
+        .env("SLACK_SIGNING_SECRET", "test-secret")
+        .env("OBSERVATORY_SLACK_API", &api_url)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect(format!("127.0.0.1:{}", port))
+                .map_err(|_| std::thread::sleep(Duration::from_millis(100)))
+                .ok()
+        })
+        .expect("server did not start");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let command = "curl -H 'Authorization: Bearer supersecrettoken123' https://example.com";
+    let body = serde_json::json!({"tool_name": "Bash", "tool_input": {"command": command}});
+    let asked = std::thread::spawn(move || {
+        send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            "/hook?event=PreToolUse",
+            Some(&body.to_string()),
+        )
+    });
+
+    // While that hook waits for a click, other hooks are answered
+    let first_call = posted.recv_timeout(Duration::from_secs(5)).unwrap();
+    let mut other = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    other
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut other_writer = other.try_clone().unwrap();
+    let started = std::time::Instant::now();
+    let (status, _) = send_request(
+        &mut other,
+        &mut other_writer,
+        "POST",
+        "/hook?event=PostToolUse",
+        Some("{}"),
     );
+    assert_eq!(status, 200);
+    assert!(started.elapsed() < Duration::from_millis(500));
+    assert!(!asked.is_finished());
 
-    let event: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
-    assert_eq!(event["_event"], "PostToolUse");
-    assert_eq!(event["tool_name"], "Edit");
-    assert!(event["_ts"].is_string());
-    assert!(event["_client"].is_string()); // TCP includes _client
+    // Nobody clicked: the "ask" stands
+    let (status, body) = asked.join().unwrap();
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""permissionDecision":"ask""#), "{}", body);
+    // The message and its update show the command as the outputs do
+    let update = posted.recv_timeout(Duration::from_secs(5)).unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let calls = [first_call, update];
+    for call in &calls {
+        assert!(!call.contains("supersecrettoken123"), "{}", call);
+        assert!(call.contains("[REDACTED:authorization]"), "{}", call);
+    }
 }
 
 #[test]
-fn test_tcp_404_for_get_hook() {
+#[cfg(feature = "forwarders")]
+fn test_tcp_decision_webhook_and_fallback() {
+    // A stand-in policy service: denies `rm`, stalls on anything else
+    let policy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let policy_port = policy.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        for stream in policy.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = String::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.push_str(&String::from_utf8_lossy(&buf[..n]));
+                let complete = request.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                    head.lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|v| v.trim().to_string())
+                        })
+                        .and_then(|len| len.parse::<usize>().ok())
+                        .is_some_and(|len| body.len() >= len)
+                });
+                if n == 0 || complete {
+                    break;
+                }
+            }
+            assert!(request.contains("X-Observatory-Event: PreToolUse"));
+            // The service gets the event masked like the outputs; a leak
+            // panics here and the hook gets the fallback instead
+            assert!(!request.contains("supersecrettoken123"), "{}", request);
+            if !request.contains("\"rm ") {
+                std::thread::sleep(Duration::from_secs(2));
+                continue;
+            }
+            let body = r#"{"hookSpecificOutput":{"hookEventName":"PreToolUse","permissionDecision":"deny","permissionDecisionReason":"central policy"}}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
     let port = unique_port();
-    let mut child = start_tcp_server(port);
+    let url = format!("http://127.0.0.1:{}/decide", policy_port);
+    let child = start_tcp_server_with(
+        port,
+        &[
+            "--decision-webhook",
+            &url,
+            "--decision-timeout",
+            "500ms",
+            "--decision-fallback",
+            "ask",
+        ],
+    );
 
+    // A Notification can't be decided, so the service never hears of it
+    // (if it did, its assert would end it and the next answer be the fallback)
     let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
     stream
         .set_read_timeout(Some(Duration::from_secs(5)))
         .unwrap();
     let mut writer = stream.try_clone().unwrap();
-
-    let (status, _) = send_request(
+    let notification = send_request(
         &mut stream,
         &mut writer,
-        "GET",
-        "/hook?event=PreToolUse",
-        None,
+        "POST",
+        "/hook?event=Notification",
+        Some(r#"{"message": "waiting"}"#),
     );
-    assert_eq!(status, 404);
+    assert_eq!(notification, (200, String::new()));
 
-    child.kill().unwrap();
-    let _ = child.wait();
+    let mut decisions = Vec::new();
+    for command in [
+        "rm -rf build --header 'Authorization: Bearer supersecrettoken123'",
+        "make deploy",
+    ] {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let body = serde_json::json!({"tool_name": "Bash", "tool_input": {"command": command}});
+        let (status, body) = send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            "/hook?event=PreToolUse",
+            Some(&body.to_string()),
+        );
+        assert_eq!(status, 200);
+        let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+        decisions.push(response["hookSpecificOutput"].clone());
+    }
+
+    // Answered by the service, passed through as-is
+    assert_eq!(decisions[0]["permissionDecision"], "deny");
+    assert_eq!(decisions[0]["permissionDecisionReason"], "central policy");
+    // The service timed out: the fallback answers
+    assert_eq!(decisions[1]["permissionDecision"], "ask");
+    assert!(decisions[1]["permissionDecisionReason"]
+        .as_str()
+        .unwrap()
+        .contains("Decision service unavailable"));
+
+    let output = stop_server(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rules: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<serde_json::Value>(line).unwrap()["_decision"]["rule"].clone()
+        })
+        .collect();
+    assert_eq!(
+        rules,
+        [
+            serde_json::Value::Null,
+            "webhook".into(),
+            "webhook fallback".into()
+        ]
+    );
 }
 
 // === UNIX SOCKET INTEGRATION TESTS ===
 
 /// Start a Unix socket server and return (child, socket_path).
 fn start_unix_server(socket_path: &str) -> Child {
+    start_unix_server_with(socket_path, &[])
+}
+
+fn start_unix_server_with(socket_path: &str, extra_args: &[&str]) -> Child {
     // Clean up any stale socket
     let _ = std::fs::remove_file(socket_path);
 
-    let child = Command::new(binary_path())
+    let mut child = Command::new(binary_path())
         .arg("unix")
         .arg("--socket")
         .arg(socket_path)
+        .args(NO_REDACT)
+        .args(extra_args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
@@ -244,13 +2225,13 @@ fn start_unix_server(socket_path: &str) -> Child {
 
     // Wait for socket file to appear and be connectable
     for _ in 0..50 {
-        if std::path::Path::new(socket_path).exists() {
-            if UnixStream::connect(socket_path).is_ok() {
-                return child;
-            }
+        if std::path::Path::new(socket_path).exists() && UnixStream::connect(socket_path).is_ok() {
+            return child;
         }
         std::thread::sleep(Duration::from_millis(100));
     }
+    let _ = child.kill();
+    let _ = child.wait();
     panic!(
         "Unix socket server did not start within 5 seconds at {}",
         socket_path
@@ -272,6 +2253,7 @@ fn test_unix_health_returns_ok() {
     assert_eq!(status, 200);
     let parsed: serde_json::Value = serde_json::from_str(&body).unwrap();
     assert_eq!(parsed["status"], "ok");
+    assert_eq!(parsed["transport"], "unix");
 
     child.kill().unwrap();
     let _ = child.wait();
@@ -308,7 +2290,7 @@ fn test_unix_hook_returns_200() {
 #[test]
 fn test_unix_peer_credentials() {
     let path = unique_socket_path();
-    let mut child = start_unix_server(&path);
+    let child = start_unix_server(&path);
 
     // Send a hook event
     {
@@ -332,13 +2314,16 @@ fn test_unix_peer_credentials() {
     std::thread::sleep(Duration::from_millis(500));
 
     // Kill and read stdout
-    child.kill().unwrap();
-    let output = child.wait_with_output().unwrap();
+    let output = stop_server(child);
     let stdout = String::from_utf8_lossy(&output.stdout);
     let _ = std::fs::remove_file(&path);
 
     // Parse JSONL output
-    let lines: Vec<&str> = stdout.trim().split('\n').filter(|l| !l.is_empty()).collect();
+    let lines: Vec<&str> = stdout
+        .trim()
+        .split('\n')
+        .filter(|l| !l.is_empty())
+        .collect();
     assert!(!lines.is_empty(), "Expected JSONL output on stdout");
 
     let event: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
@@ -362,7 +2347,7 @@ fn test_unix_peer_credentials() {
 #[test]
 fn test_unix_multiple_events() {
     let path = unique_socket_path();
-    let mut child = start_unix_server(&path);
+    let child = start_unix_server(&path);
 
     let events = ["SessionStart", "PreToolUse", "PostToolUse", "SessionEnd"];
     for event_name in &events {
@@ -385,12 +2370,15 @@ fn test_unix_multiple_events() {
     // Give server time to flush
     std::thread::sleep(Duration::from_millis(500));
 
-    child.kill().unwrap();
-    let output = child.wait_with_output().unwrap();
+    let output = stop_server(child);
     let stdout = String::from_utf8_lossy(&output.stdout);
     let _ = std::fs::remove_file(&path);
 
-    let lines: Vec<&str> = stdout.trim().split('\n').filter(|l| !l.is_empty()).collect();
+    let lines: Vec<&str> = stdout
+        .trim()
+        .split('\n')
+        .filter(|l| !l.is_empty())
+        .collect();
     assert_eq!(lines.len(), 4, "Expected 4 events, got {}", lines.len());
 
     for (i, event_name) in events.iter().enumerate() {
@@ -398,3 +2386,242 @@ fn test_unix_multiple_events() {
         assert_eq!(event["_event"], *event_name);
     }
 }
+
+#[test]
+fn test_unix_summary_every_prints_to_stderr() {
+    let path = unique_socket_path();
+    let out_path = format!("{}.out", path);
+    // Output only to the socket: the terminal would otherwise show nothing
+    let child = start_unix_server_with(
+        &path,
+        &["--output-socket", &out_path, "--summary-every", "1s"],
+    );
+    for (event, session) in [("PreToolUse", "s1"), ("PreToolUse", "s2"), ("Stop", "s1")] {
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let body = format!(r#"{{"session_id":"{}"}}"#, session);
+        let hook = format!("/hook?event={}", event);
+        assert_eq!(
+            send_request(&mut stream, &mut writer, "POST", &hook, Some(&body)).0,
+            200
+        );
+    }
+    std::thread::sleep(Duration::from_millis(2500));
+
+    let output = stop_server(child);
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&out_path);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("last 1s: 3 events (Pre:2 Stop:1), 2 sessions, 0 readers\n"),
+        "{}",
+        stderr
+    );
+    // Quiet intervals are reported too
+    assert!(
+        stderr.contains("last 1s: 0 events, 0 sessions, 0 readers\n"),
+        "{}",
+        stderr
+    );
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_unix_heartbeat_reaches_readers_and_stalls_are_reported() {
+    let path = unique_socket_path();
+    let out_path = format!("{}.out", path);
+    let child = start_unix_server_with(
+        &path,
+        &[
+            "--output-socket",
+            &out_path,
+            "--only",
+            "Stop",
+            "--heartbeat",
+            "1s",
+            "--stall-after",
+            "1s",
+        ],
+    );
+    let reader = UnixStream::connect(&out_path).unwrap();
+    std::thread::sleep(Duration::from_millis(200)); // let the server accept it
+
+    // Mid-turn, then silence
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let body = r#"{"session_id":"s1","tool_name":"Bash"}"#;
+    let (status, _) = send_request(
+        &mut stream,
+        &mut writer,
+        "POST",
+        "/hook?event=PreToolUse",
+        Some(body),
+    );
+    assert_eq!(status, 200);
+
+    // --only kept the event from the reader; the heartbeat still comes,
+    // and its _last_seq shows what the reader didn't get
+    reader
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut lines = std::io::BufRead::lines(std::io::BufReader::new(reader));
+    let beat: serde_json::Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+    assert_eq!(beat["_event"], "_heartbeat");
+    assert_eq!(beat["_last_seq"], 1);
+    std::thread::sleep(Duration::from_millis(1000));
+
+    let output = stop_server(child);
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&out_path);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stderr
+            .matches("WARN No hook events while a session is in a turn")
+            .count(),
+        1,
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_unix_slow_output_reader_gets_every_line() {
+    let path = unique_socket_path();
+    let out_path = format!("{}.out", path);
+    let mut child = start_unix_server_with(&path, &["--output-socket", &out_path]);
+    let reader = UnixStream::connect(&out_path).unwrap();
+    std::thread::sleep(Duration::from_millis(200)); // let the server accept it
+
+    // ~600KB of events while the reader isn't reading: several times what
+    // the socket buffer holds
+    let payload = format!(r#"{{"blob":"{}"}}"#, "x".repeat(2000));
+    for _ in 0..300 {
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let (status, _) = send_request(
+            &mut stream,
+            &mut writer,
+            "POST",
+            "/hook?event=PostToolUse",
+            Some(&payload),
+        );
+        assert_eq!(status, 200);
+    }
+
+    // The reader wasn't dropped: everything arrives once it reads, in
+    // order and without a gap in _seq
+    reader
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut lines = std::io::BufRead::lines(std::io::BufReader::new(reader));
+    for i in 0..300 {
+        let line = lines
+            .next()
+            .unwrap_or_else(|| panic!("reader closed after {} lines", i));
+        let event: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+        assert_eq!(event["_event"], "PostToolUse");
+        assert_eq!(event["_seq"], i + 1);
+    }
+
+    child.kill().unwrap();
+    let _ = child.wait();
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&out_path);
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn test_bench_against_unix_server() {
+    let path = unique_socket_path();
+    let mut child = start_unix_server_with(&path, &[]);
+
+    let output = Command::new(binary_path())
+        .args(["bench", "--target", &format!("unix://{}", path)])
+        .args(["--rate", "200", "--duration", "1s", "--connections", "3"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        report.contains("Sent:        200 PostToolUse events"),
+        "{}",
+        report
+    );
+    assert!(report.contains("Errors:      0"), "{}", report);
+    assert!(report.contains("Latency:     p50 "), "{}", report);
+
+    child.kill().unwrap();
+    let server = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&path);
+    // Every synthetic event reached the observatory
+    assert_eq!(String::from_utf8_lossy(&server.stdout).lines().count(), 200);
+}
+
+// === CLIENT LIBRARY TESTS ===
+
+#[test]
+#[cfg(feature = "client")]
+fn test_client_library_and_send_subcommand() {
+    use rust_observatory::client::ObservatoryClient;
+
+    let port = unique_port();
+    let mut tcp = start_tcp_server(port);
+    let path = unique_socket_path();
+    let mut unix = start_unix_server(&path);
+
+    let payload = serde_json::json!({"tool_name": "Bash"});
+    let response = ObservatoryClient::tcp(format!("127.0.0.1:{}", port))
+        .send("PreToolUse", &payload)
+        .unwrap();
+    assert_eq!((response.status, response.body.as_str()), (200, ""));
+    let response = ObservatoryClient::unix(&path)
+        .send("PreToolUse", &payload)
+        .unwrap();
+    assert_eq!(response.status, 200);
+
+    // `send` reads the payload from stdin, like a hook command
+    let mut send = Command::new(binary_path())
+        .args([
+            "send",
+            "Stop",
+            "--port",
+            &port.to_string(),
+            "--timeout",
+            "5s",
+        ])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    send.stdin
+        .take()
+        .unwrap()
+        .write_all(br#"{"stop_hook_active":false}"#)
+        .unwrap();
+    assert!(send.wait().unwrap().success());
+
+    std::thread::sleep(Duration::from_millis(500));
+    tcp.kill().unwrap();
+    unix.kill().unwrap();
+    let tcp_out = tcp.wait_with_output().unwrap();
+    let unix_out = unix.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    let events: Vec<serde_json::Value> = String::from_utf8_lossy(&tcp_out.stdout)
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["tool_name"], "Bash");
+    assert_eq!(events[1]["_event"], "Stop");
+    assert_eq!(events[1]["stop_hook_active"], false);
+    assert!(String::from_utf8_lossy(&unix_out.stdout).contains(r#""_event":"PreToolUse""#));
+}